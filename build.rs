@@ -7,32 +7,27 @@ use std::{
 
 use slang::Downcast;
 
+// Names of the compute kernels that get linked into a module's SPIR-V if they exist
+const ENTRY_POINTS: &[&str] = &["main", "update", "release", "allocate", "invalidate", "compact", "prepare", "propagate", "histogram", "exposure", "downsample", "upsample", "grade", "vignette", "grain", "estimate", "atrous", "modulate", "encode"];
+
 fn load_module(session: &mut slang::Session, file_name: &str) {
     let module = session.load_module(&format!("{file_name}.slang")).unwrap();
-    let entry_point = module.find_entry_point_by_name("main");
 
-    let entry_point = if let Some(entry_point) = entry_point {
-        entry_point
-    } else {
+    // Modules without a main entry point are include-only files
+    if module.find_entry_point_by_name("main").is_none() {
         return;
-    };
-
-    let program = if let Some(entry_point2) = module.find_entry_point_by_name("update") {
-        session
-            .create_composite_component_type(&[
-                module.downcast().clone(),
-                entry_point.downcast().clone(),
-                entry_point2.downcast().clone()
-            ])
-            .unwrap()
-    } else {
-        session
-            .create_composite_component_type(&[
-                module.downcast().clone(),
-                entry_point.downcast().clone(),
-            ])
-            .unwrap()
-    };
+    }
+
+    let mut components = vec![module.downcast().clone()];
+    for name in ENTRY_POINTS {
+        if let Some(entry_point) = module.find_entry_point_by_name(name) {
+            components.push(entry_point.downcast().clone());
+        }
+    }
+
+    let program = session
+        .create_composite_component_type(&components)
+        .unwrap();
 
     let linked_program = program.link().unwrap();
     let shader_bytecode = linked_program.target_code(0).unwrap();
@@ -0,0 +1,273 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::{image, pipeline::PushConstants8};
+
+// Transmittance of the cloud layer towards the sun, one float per texel
+pub const CLOUD_SHADOW_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+// Size of the cloud shadow map in texels. Must match the value in clouds.slang
+pub const CLOUD_SHADOW_RESOLUTION: u32 = 256;
+
+// Controls the volumetric cloud layer drawn over the sky and the shadows it casts on the voxels
+pub struct CloudSettings {
+    pub enabled: bool,
+
+    // Height of the bottom of the layer and how thick it is, in voxels
+    pub altitude: f32,
+    pub thickness: f32,
+
+    // Fraction of the sky covered by clouds (0 to 1)
+    pub coverage: f32,
+
+    // Extinction per voxel at the densest parts of the clouds
+    pub density: f32,
+
+    // Size of the noise features, in voxels
+    pub scale: f32,
+
+    // Voxels per second on the X and Z axes
+    pub wind: vek::Vec2<f32>,
+
+    // How fast the clouds change shape, in noise units per second
+    pub evolution: f32,
+
+    // Steps taken along the view rays and towards the sun
+    pub steps: u32,
+    pub light_steps: u32,
+
+    // Width of the area around the camera covered by the shadow map, in voxels
+    pub shadow_extent: f32,
+
+    // How much of the sun light the thickest clouds block (0 to 1)
+    pub shadow_strength: f32,
+
+    // Change per second while holding the coverage and wind keys
+    pub coverage_speed: f32,
+    pub wind_acceleration: f32,
+    pub wind_turn_speed: f32,
+
+    // Advanced by tick(), moves the noise with the wind and evolves it
+    pub offset: vek::Vec2<f32>,
+    pub phase: f32,
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            altitude: 160.0,
+            thickness: 48.0,
+            coverage: 0.45,
+            density: 0.08,
+            scale: 96.0,
+            wind: vek::Vec2::new(6.0, 2.0),
+            evolution: 0.02,
+            steps: 32,
+            light_steps: 6,
+            shadow_extent: 512.0,
+            shadow_strength: 0.85,
+            coverage_speed: 0.25,
+            wind_acceleration: 8.0,
+            wind_turn_speed: 1.0,
+            offset: vek::Vec2::zero(),
+            phase: 0.0,
+        }
+    }
+}
+
+impl CloudSettings {
+    // Called once per tick with the fixed tick duration
+    pub fn tick(&mut self, delta: f32) {
+        self.offset += self.wind * delta;
+        self.phase += self.evolution * delta;
+    }
+
+    // Rotates the wind around the Y axis and scales its speed, it never goes backwards
+    pub fn steer(&mut self, angle: f32, acceleration: f32) {
+        let speed = (self.wind.magnitude() + acceleration).max(0.0);
+        let heading = self.wind.y.atan2(self.wind.x) + angle;
+        self.wind = vek::Vec2::new(heading.cos(), heading.sin()) * speed;
+    }
+
+    // The shadow map follows the camera, snapped to whole texels so the shadows don't shimmer when moving
+    pub fn uniform(&self, camera: vek::Vec3<f32>) -> Clouds {
+        let texel = self.shadow_extent / CLOUD_SHADOW_RESOLUTION as f32;
+        let center = vek::Vec2::new(camera.x, camera.z).map(|x| (x / texel).floor() * texel);
+
+        Clouds {
+            offset: self.offset,
+            phase: self.phase,
+            coverage: self.coverage,
+            altitude: self.altitude,
+            thickness: self.thickness,
+            density: self.density,
+            scale: self.scale,
+            shadow_origin: center - self.shadow_extent * 0.5,
+            shadow_extent: self.shadow_extent,
+            steps: self.steps,
+            enabled: (self.enabled && self.coverage > 0.0) as u32,
+            shadow_strength: self.shadow_strength,
+            light_steps: self.light_steps,
+            _padding: 0,
+        }
+    }
+}
+
+// Must match Clouds in clouds.slang
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub struct Clouds {
+    pub offset: vek::Vec2<f32>,
+    pub phase: f32,
+    pub coverage: f32,
+    pub altitude: f32,
+    pub thickness: f32,
+    pub density: f32,
+    pub scale: f32,
+
+    // XZ of the corner of the shadow map and its width, in voxels
+    pub shadow_origin: vek::Vec2<f32>,
+    pub shadow_extent: f32,
+    pub steps: u32,
+    pub enabled: u32,
+    pub shadow_strength: f32,
+    pub light_steps: u32,
+    pub _padding: u32,
+}
+
+// Host visible so the wind and coverage can change every frame
+pub unsafe fn create_cloud_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<Clouds>() as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Cloud Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"cloud buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+pub fn upload_clouds(allocation: &mut Allocation, clouds: &Clouds) {
+    let raw = bytemuck::bytes_of(clouds);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+}
+
+// Gets rewritten every frame so it never needs to keep its contents
+pub unsafe fn create_cloud_shadow_image(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Image, Allocation, vk::ImageView) {
+    image::create_storage_image(
+        device,
+        allocator,
+        vek::Vec2::broadcast(CLOUD_SHADOW_RESOLUTION),
+        CLOUD_SHADOW_FORMAT,
+        binder,
+        c"cloud shadow map",
+    )
+}
+
+// Marches the cloud layer towards the sun for every texel of the shadow map, the raymarcher reads it right after
+pub unsafe fn render_cloud_shadows(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    shadow_image_view: vk::ImageView,
+    cloud_buffer: vk::Buffer,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    push_constants: PushConstants8,
+) -> vk::DescriptorSet {
+    let layouts = [descriptor_set_layout];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let descriptor_image_infos = [vk::DescriptorImageInfo::default()
+        .image_view(shadow_image_view)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .sampler(vk::Sampler::null())];
+    let descriptor_buffer_infos = [vk::DescriptorBufferInfo::default()
+        .buffer(cloud_buffer)
+        .offset(0)
+        .range(u64::MAX)];
+
+    let descriptor_writes = [
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(0)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_image_infos),
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(1)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_buffer_infos),
+    ];
+
+    device.update_descriptor_sets(&descriptor_writes, &[]);
+
+    device.cmd_bind_descriptor_sets(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &descriptor_sets,
+        &[],
+    );
+    device.cmd_bind_pipeline(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline,
+    );
+    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants));
+
+    let groups = (CLOUD_SHADOW_RESOLUTION + 15) / 16;
+    device.cmd_dispatch(cmd, groups, groups, 1);
+
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    descriptor_set
+}
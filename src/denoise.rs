@@ -0,0 +1,215 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::{gbuffer, image, pipeline::{self, PushConstants7}};
+
+// Every image of the denoiser holds 4 halfs (illumination + variance, moments + length, normal + depth)
+pub const HISTORY_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Controls the SVGF denoiser that runs on the lighting of the raymarcher before the TAA pass
+pub struct DenoiseSettings {
+    pub enabled: bool,
+
+    // Number of a-trous iterations, the filter footprint doubles with each one
+    pub iterations: u32,
+
+    // Minimum blend factor of the new frame into the history (for the illumination and its moments)
+    pub alpha: f32,
+    pub moments_alpha: f32,
+
+    // How picky the edge stopping functions are
+    pub phi_color: f32,
+    pub phi_normal: f32,
+    pub phi_depth: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            iterations: 4,
+            alpha: 0.2,
+            moments_alpha: 0.2,
+            phi_color: 4.0,
+            phi_normal: 128.0,
+            phi_depth: 1.0,
+        }
+    }
+}
+
+impl DenoiseSettings {
+    // G-buffer images the raymarcher has to write for the denoiser
+    pub fn gbuffer_outputs(&self) -> u32 {
+        if self.enabled {
+            gbuffer::GBUFFER_DEPTH | gbuffer::GBUFFER_NORMAL | gbuffer::GBUFFER_LIGHTING
+        } else {
+            0
+        }
+    }
+
+    pub fn push_constants(&self, resolution: vek::Vec2<u32>, previous_resolution: vek::Vec2<u32>, index: usize, reset: bool) -> PushConstants7 {
+        PushConstants7 {
+            resolution,
+            previous_resolution,
+            index: index as u32,
+            source: 0,
+            step: 1,
+            reset: reset as u32,
+            alpha: self.alpha,
+            moments_alpha: self.moments_alpha,
+            phi_color: self.phi_color,
+            phi_normal: self.phi_normal,
+            phi_depth: self.phi_depth,
+        }
+    }
+}
+
+// Pairs of images, one for this frame and one for the last (or for the next a-trous iteration)
+pub struct DenoiseImages {
+    pub illumination_history: [(vk::Image, Allocation, vk::ImageView); 2],
+    pub moments_history: [(vk::Image, Allocation, vk::ImageView); 2],
+    pub geometry_history: [(vk::Image, Allocation, vk::ImageView); 2],
+    pub filtered: [(vk::Image, Allocation, vk::ImageView); 2],
+}
+
+impl DenoiseImages {
+    pub fn images(&self) -> [vk::Image; 8] {
+        [
+            self.illumination_history[0].0,
+            self.illumination_history[1].0,
+            self.moments_history[0].0,
+            self.moments_history[1].0,
+            self.geometry_history[0].0,
+            self.geometry_history[1].0,
+            self.filtered[0].0,
+            self.filtered[1].0,
+        ]
+    }
+}
+
+// Same size as the G-buffer (max render resolution)
+pub unsafe fn create_denoise_images(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    extent: vek::Vec2<u32>,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> DenoiseImages {
+    let mut create = |name: &std::ffi::CStr| image::create_storage_image(device, allocator, extent, HISTORY_FORMAT, binder, name);
+
+    DenoiseImages {
+        illumination_history: [create(c"denoise illumination history 0"), create(c"denoise illumination history 1")],
+        moments_history: [create(c"denoise moments history 0"), create(c"denoise moments history 1")],
+        geometry_history: [create(c"denoise geometry history 0"), create(c"denoise geometry history 1")],
+        filtered: [create(c"denoise filtered image 0"), create(c"denoise filtered image 1")],
+    }
+}
+
+pub unsafe fn destroy_denoise_images(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    images: DenoiseImages,
+) {
+    let pairs = [images.illumination_history, images.moments_history, images.geometry_history, images.filtered];
+    for image in pairs.into_iter().flatten() {
+        image::destroy_storage_image(device, allocator, image);
+    }
+}
+
+// Temporal accumulation, variance estimation, the a-trous iterations and finally putting the albedo back in the output
+pub unsafe fn denoise(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    gbuffer: &gbuffer::GBuffer,
+    images: &DenoiseImages,
+    output_image_view: vk::ImageView,
+    pipelines: &[(vk::DescriptorSetLayout, vk::PipelineLayout, vk::Pipeline); 4],
+    settings: &DenoiseSettings,
+    push_constants: PushConstants7,
+) -> vk::DescriptorSet {
+    let layouts = [pipelines[0].0];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let image_info = |view: vk::ImageView| {
+        vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null())
+    };
+
+    let descriptor_image_infos = [
+        vec![image_info(gbuffer.illumination.2)],
+        vec![image_info(gbuffer.albedo.2)],
+        vec![image_info(gbuffer.depth.2)],
+        vec![image_info(gbuffer.normal.2)],
+        vec![image_info(gbuffer.motion.2)],
+        images.illumination_history.iter().map(|image| image_info(image.2)).collect(),
+        images.moments_history.iter().map(|image| image_info(image.2)).collect(),
+        images.geometry_history.iter().map(|image| image_info(image.2)).collect(),
+        images.filtered.iter().map(|image| image_info(image.2)).collect(),
+        vec![image_info(output_image_view)],
+    ];
+
+    let descriptor_writes = descriptor_image_infos.iter().enumerate().map(|(binding, infos)| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(infos.len() as u32)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(binding as u32)
+            .dst_set(descriptor_set)
+            .image_info(infos)
+    }).collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&descriptor_writes, &[]);
+
+    let groups = (push_constants.resolution + 15) / 16;
+    let dispatch = |kernel: usize, push_constants: &PushConstants7| {
+        let (_, pipeline_layout, pipeline) = pipelines[kernel];
+
+        // Each pass reads what the previous one wrote (starting with the raymarcher output)
+        let barrier = vk::MemoryBarrier2::default()
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+        let barriers = [barrier];
+        let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+        device.cmd_pipeline_barrier2(cmd, &dep);
+
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &descriptor_sets,
+            &[],
+        );
+        device.cmd_bind_pipeline(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline,
+        );
+        device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(push_constants));
+        device.cmd_dispatch(cmd, groups.x, groups.y, 1);
+    };
+
+    // Temporal pass writes filtered[0], the variance estimation copies (or blurs) it into filtered[1]
+    dispatch(pipeline::DENOISE_TEMPORAL, &push_constants);
+    dispatch(pipeline::DENOISE_ESTIMATE, &push_constants);
+
+    // The first iteration also writes the history, so we always need at least one
+    let mut source = 1;
+    for iteration in 0..settings.iterations.max(1) {
+        dispatch(pipeline::DENOISE_ATROUS, &PushConstants7 { source, step: 1 << iteration, ..push_constants });
+        source = 1 - source;
+    }
+
+    dispatch(pipeline::DENOISE_MODULATE, &PushConstants7 { source, ..push_constants });
+
+    descriptor_set
+}
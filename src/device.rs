@@ -22,7 +22,9 @@ pub unsafe fn create_device_and_queue(
         .queue_family_index(queue_family_index);
     let queue_create_infos = [queue_create_info];
 
-    let device_features = vk::PhysicalDeviceFeatures::default();
+    let device_features = vk::PhysicalDeviceFeatures::default()
+        .shader_storage_image_extended_formats(true)
+        .shader_storage_image_array_dynamic_indexing(true);
     let mut device_features_13 = vk::PhysicalDeviceVulkan13Features::default()
         .synchronization2(true);
     let mut device_features_12 = vk::PhysicalDeviceVulkan12Features::default()
@@ -0,0 +1,354 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::image;
+
+// Environment map that gets used when ENVIRONMENT_MAP is not set
+pub const DEFAULT_ENVIRONMENT_PATH: &str = "sky.hdr";
+
+// Halfs are plenty for the sky and take half the memory of floats
+pub const ENVIRONMENT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Max number of texels per row we look at when integrating the ambient light, bigger maps get skipped through
+const AMBIENT_SAMPLES: u32 = 512;
+
+// Controls the equirectangular environment map, which replaces the procedural sky() when enabled
+pub struct EnvironmentSettings {
+    pub enabled: bool,
+
+    // Around the Y axis, in radians
+    pub rotation: f32,
+
+    // Radians per second while holding the rotation keys
+    pub rotation_speed: f32,
+
+    // Multiplier of everything coming from the map
+    pub intensity: f32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rotation: 0.0,
+            rotation_speed: 0.5,
+            intensity: 1.0,
+        }
+    }
+}
+
+impl EnvironmentSettings {
+    // Disabled no matter what if we didn't manage to load a map
+    pub fn uniform(&self, ambient: Option<&[vek::Vec4<f32>; 6]>) -> Environment {
+        Environment {
+            ambient: ambient.copied().unwrap_or([vek::Vec4::zero(); 6]),
+            rotation: self.rotation,
+            intensity: self.intensity,
+            enabled: (self.enabled && ambient.is_some()) as u32,
+            _padding: 0,
+        }
+    }
+}
+
+// Must match Environment in lighting.slang
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct Environment {
+    // Light reaching a surface facing +X, -X, +Y, -Y, +Z and -Z (before the rotation), already divided by pi
+    pub ambient: [vek::Vec4<f32>; 6],
+    pub rotation: f32,
+    pub intensity: f32,
+    pub enabled: u32,
+    pub _padding: u32,
+}
+
+// Linear RGB texels of an equirectangular .hdr image, top row first
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<vek::Vec3<f32>>,
+}
+
+fn rgbe_to_linear(rgbe: [u8; 4]) -> vek::Vec3<f32> {
+    if rgbe[3] == 0 {
+        return vek::Vec3::zero();
+    }
+
+    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+    vek::Vec3::new(rgbe[0] as f32, rgbe[1] as f32, rgbe[2] as f32) * scale
+}
+
+// Saturates instead of going to infinity, the sun in some maps is brighter than what halfs can hold
+fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let value = value.abs();
+
+    if value.is_nan() {
+        return 0x7e00;
+    } else if value >= 65504.0 {
+        return sign | 0x7bff;
+    } else if value < 6.1035156e-5 {
+        // Subnormals, in units of 2^-24
+        return sign | (value * 16777216.0).round() as u16;
+    }
+
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) as i32 - 127 + 15) as u32;
+    let mantissa = bits & 0x7fffff;
+
+    // Round to nearest, a carry into the exponent is still the right value
+    let half = (exponent << 10) + (mantissa >> 13) + ((mantissa >> 12) & 1);
+    sign | half.min(0x7bff) as u16
+}
+
+impl EnvironmentMap {
+    // Radiance .hdr files, both flat and run length encoded scanlines. Only the usual -Y H +X W orientation
+    // Images we couldn't create (empty or bigger than max_extent on either side) get rejected too
+    pub fn parse(bytes: &[u8], max_extent: u32) -> Result<Self, String> {
+        let mut cursor = 0;
+        let mut line = || {
+            let start = cursor;
+            let end = bytes[start..].iter().position(|x| *x == b'\n').map(|x| start + x).ok_or("unexpected end of the header".to_string())?;
+            cursor = end + 1;
+            Ok::<_, String>(String::from_utf8_lossy(&bytes[start..end]).trim().to_string())
+        };
+
+        let magic = line()?;
+        if magic != "#?RADIANCE" && magic != "#?RGBE" {
+            return Err(format!("not a radiance file (starts with {})", magic));
+        }
+
+        loop {
+            let header = line()?;
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some(format) = header.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(format!("{} is not supported", format));
+                }
+            }
+        }
+
+        let resolution = line()?;
+        let words = resolution.split_whitespace().collect::<Vec<_>>();
+        let (height, width) = match words[..] {
+            ["-Y", height, "+X", width] => (height.parse::<u32>(), width.parse::<u32>()),
+            _ => return Err(format!("unsupported resolution line {}", resolution)),
+        };
+        let height = height.map_err(|err| err.to_string())?;
+        let width = width.map_err(|err| err.to_string())?;
+        if width == 0 || height == 0 {
+            return Err(format!("empty {}x{} image", width, height));
+        } else if width > max_extent || height > max_extent {
+            return Err(format!("{}x{} is bigger than the {} texels the device supports", width, height, max_extent));
+        }
+
+        let mut data = &bytes[cursor..];
+        // A header lying about the size shouldn't make us reserve gigabytes, the scanlines run out way before that
+        let mut texels = Vec::<vek::Vec3<f32>>::with_capacity((width as usize * height as usize).min(data.len()));
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        let truncated = || "scanline data is truncated".to_string();
+
+        for _ in 0..height {
+            let encoded = width >= 8 && width < 0x8000 && data.len() >= 4 && data[0] == 2 && data[1] == 2 && ((data[2] as u32) << 8 | data[3] as u32) == width;
+
+            if encoded {
+                data = &data[4..];
+
+                // Each channel is stored separately as runs or literals
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < width as usize {
+                        let count = *data.first().ok_or_else(truncated)? as usize;
+                        if count > 128 {
+                            let count = count - 128;
+                            let value = *data.get(1).ok_or_else(truncated)?;
+                            if x + count > width as usize {
+                                return Err("run goes past the end of the scanline".to_string());
+                            }
+
+                            scanline[x..x + count].iter_mut().for_each(|texel| texel[channel] = value);
+                            data = &data[2..];
+                            x += count;
+                        } else {
+                            let values = data.get(1..1 + count).ok_or_else(truncated)?;
+                            if count == 0 || x + count > width as usize {
+                                return Err("invalid literal in the scanline".to_string());
+                            }
+
+                            scanline[x..x + count].iter_mut().zip(values).for_each(|(texel, value)| texel[channel] = *value);
+                            data = &data[1 + count..];
+                            x += count;
+                        }
+                    }
+                }
+            } else {
+                let raw = data.get(..width as usize * 4).ok_or_else(truncated)?;
+                scanline.iter_mut().zip(raw.chunks_exact(4)).for_each(|(texel, rgbe)| *texel = [rgbe[0], rgbe[1], rgbe[2], rgbe[3]]);
+                data = &data[width as usize * 4..];
+            }
+
+            texels.extend(scanline.iter().map(|rgbe| rgbe_to_linear(*rgbe)));
+        }
+
+        Ok(Self { width, height, texels })
+    }
+
+    // Loads the map from ENVIRONMENT_MAP (or the default path), we stick to the procedural sky if that fails
+    // No map at all is the usual case since we don't ship one. max_extent is the biggest 2D image the device supports
+    pub fn load(max_extent: u32) -> Option<Self> {
+        let path = std::env::var("ENVIRONMENT_MAP").unwrap_or(DEFAULT_ENVIRONMENT_PATH.to_string());
+
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("no environment map at {}, using the procedural sky", path);
+                return None;
+            }
+            Err(err) => {
+                log::error!("could not read environment map {}: {}", path, err);
+                return None;
+            }
+        };
+
+        match Self::parse(&bytes, max_extent) {
+            Ok(map) => {
+                log::info!("loaded {}x{} environment map from {}", map.width, map.height, path);
+                Some(map)
+            }
+            Err(err) => {
+                log::error!("could not parse environment map {}: {}", path, err);
+                None
+            }
+        }
+    }
+
+    // Same mapping as environment() in lighting.slang
+    fn direction(&self, x: u32, y: u32) -> vek::Vec3<f32> {
+        let phi = ((x as f32 + 0.5) / self.width as f32 - 0.5) * std::f32::consts::TAU;
+        let theta = (y as f32 + 0.5) / self.height as f32 * std::f32::consts::PI;
+        vek::Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    // Cosine weighted integral of the map around each axis (an ambient cube)
+    pub fn ambient(&self) -> [vek::Vec4<f32>; 6] {
+        let axes = [
+            vek::Vec3::unit_x(), -vek::Vec3::unit_x(),
+            vek::Vec3::unit_y(), -vek::Vec3::unit_y(),
+            vek::Vec3::unit_z(), -vek::Vec3::unit_z(),
+        ];
+
+        let step = (self.width / AMBIENT_SAMPLES).max(1);
+        let mut sums = [vek::Vec3::<f32>::zero(); 6];
+
+        for y in (0..self.height).step_by(step as usize) {
+            for x in (0..self.width).step_by(step as usize) {
+                let dir = self.direction(x, y);
+                let theta = (y as f32 + 0.5) / self.height as f32 * std::f32::consts::PI;
+                let solid_angle = theta.sin() * (std::f32::consts::TAU / self.width as f32) * (std::f32::consts::PI / self.height as f32) * (step * step) as f32;
+                let radiance = self.texels[(y * self.width + x) as usize];
+
+                for (axis, sum) in axes.iter().zip(sums.iter_mut()) {
+                    *sum += radiance * dir.dot(*axis).max(0.0) * solid_angle;
+                }
+            }
+        }
+
+        sums.map(|sum| (sum / std::f32::consts::PI).with_w(0.0))
+    }
+
+    // RGBA halfs, what ENVIRONMENT_FORMAT expects
+    pub fn halfs(&self) -> Vec<u16> {
+        self.texels.iter().flat_map(|texel| [to_half(texel.x), to_half(texel.y), to_half(texel.z), to_half(1.0)]).collect()
+    }
+}
+
+// Linear filtering since the maps are usually lower resolution than the screen, wraps around horizontally only
+pub unsafe fn create_environment_sampler(device: &ash::Device) -> vk::Sampler {
+    let sampler_create_info = vk::SamplerCreateInfo::default()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .max_lod(0.0);
+    device.create_sampler(&sampler_create_info, None).unwrap()
+}
+
+// Without a map we still need something to bind, so it's a single black texel
+pub unsafe fn create_environment_image(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    queue_family_index: u32,
+    pool: vk::CommandPool,
+    queue: vk::Queue,
+    map: Option<&EnvironmentMap>,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Image, Allocation, vk::ImageView) {
+    let (extent, halfs) = match map {
+        Some(map) => (vek::Vec2::new(map.width, map.height), map.halfs()),
+        None => (vek::Vec2::one(), vec![0u16; 4]),
+    };
+
+    image::create_sampled_image(
+        device,
+        allocator,
+        queue_family_index,
+        pool,
+        queue,
+        extent,
+        1,
+        ENVIRONMENT_FORMAT,
+        vk::ImageViewType::TYPE_2D,
+        bytemuck::cast_slice(&halfs),
+        binder,
+        c"environment map",
+    )
+}
+
+// Host visible so the rotation can change every frame
+pub unsafe fn create_environment_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<Environment>() as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Environment Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"environment buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+pub fn upload_environment(allocation: &mut Allocation, environment: &Environment) {
+    let raw = bytemuck::bytes_of(environment);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+}
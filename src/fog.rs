@@ -0,0 +1,153 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::pipeline::PushConstants8;
+
+// Volumetric height fog and god rays, passed to the raymarcher through the render push constants
+pub struct FogSettings {
+    pub enabled: bool,
+    pub color: vek::Vec3<f32>,
+
+    // Density at height 0, decreasing exponentially with height_falloff
+    pub density: f32,
+    pub height_falloff: f32,
+
+    // Henyey-Greenstein anisotropy of the sun light scattering (0 is isotropic, close to 1 is mostly forward)
+    pub anisotropy: f32,
+
+    // Multiplier of the in-scattered sun light
+    pub scattering: f32,
+
+    // Number of steps taken along each primary ray
+    pub steps: u32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            color: vek::Vec3::new(0.8, 0.85, 0.9),
+            density: 0.01,
+            height_falloff: 0.08,
+            anisotropy: 0.6,
+            scattering: 1.0,
+            steps: 16,
+        }
+    }
+}
+
+impl FogSettings {
+    // xyz: color, w: density
+    pub fn color(&self) -> vek::Vec4<f32> {
+        self.color.with_w(if self.enabled { self.density } else { 0.0 })
+    }
+
+    pub fn steps(&self) -> u32 {
+        if self.enabled { self.steps } else { 0 }
+    }
+}
+
+// Single float4 written by the fog ambient kernel every frame, never touched by the CPU
+pub unsafe fn create_fog_ambient_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<vek::Vec4<f32>>() as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Fog Ambient Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"fog ambient buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Evaluates the sky (or environment map) light coming from above once so the fog doesn't do it for every pixel
+// The environment buffer must already contain the settings of this frame
+pub unsafe fn render_fog_ambient(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    ambient_buffer: vk::Buffer,
+    environment_buffer: vk::Buffer,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    push_constants: PushConstants8,
+) -> vk::DescriptorSet {
+    let layouts = [descriptor_set_layout];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let descriptor_buffer_infos = [ambient_buffer, environment_buffer].map(|buffer| {
+        [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(0)
+            .range(u64::MAX)]
+    });
+
+    let descriptor_writes = [0, 1].map(|binding| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(binding)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_buffer_infos[binding as usize])
+    });
+
+    device.update_descriptor_sets(&descriptor_writes, &[]);
+
+    device.cmd_bind_descriptor_sets(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &descriptor_sets,
+        &[],
+    );
+    device.cmd_bind_pipeline(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline,
+    );
+    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants));
+    device.cmd_dispatch(cmd, 1, 1, 1);
+
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    descriptor_set
+}
@@ -0,0 +1,108 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::image;
+
+// Bits of the gbuffer_outputs push constant, each one enables a write in the raymarcher
+// The motion vectors are always written since the TAA pass needs them
+pub const GBUFFER_DEPTH: u32 = 1 << 0;
+pub const GBUFFER_NORMAL: u32 = 1 << 1;
+pub const GBUFFER_MATERIAL: u32 = 1 << 2;
+pub const GBUFFER_VOXEL: u32 = 1 << 3;
+pub const GBUFFER_LIGHTING: u32 = 1 << 4;
+
+// Linear depth along the camera forward axis, sky pixels get SKY_DEPTH from raymarcher.slang
+pub const DEPTH_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+// World space normal of the first hit face, zero for the sky or when starting inside a voxel
+pub const NORMAL_FORMAT: vk::Format = vk::Format::R8G8B8A8_SNORM;
+
+// Raw voxel bits (see Voxel in other.slang) of the first hit, 0 for the sky
+pub const MATERIAL_FORMAT: vk::Format = vk::Format::R8_UINT;
+
+// Integer coordinate of the first hit voxel, w is 1 for hits and 0 for the sky
+pub const VOXEL_FORMAT: vk::Format = vk::Format::R16G16B16A16_UINT;
+
+// Noisy light of the first diffuse hit and the albedo (with the glass tint and fog) it gets multiplied by
+// The final color is albedo * illumination plus whatever the fog scattered in, that's what the denoiser relies on
+pub const LIGHTING_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Motion (in UVs) of whatever is visible in each pixel since the last frame
+pub const MOTION_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+// Which of the optional G-buffer images the raymarcher fills in
+// Everything is off by default so the raymarcher doesn't pay for writes nobody reads
+#[derive(Default)]
+pub struct GBufferSettings {
+    pub depth: bool,
+    pub normal: bool,
+    pub material: bool,
+    pub voxel: bool,
+    pub lighting: bool,
+}
+
+impl GBufferSettings {
+    pub fn outputs(&self) -> u32 {
+        let mut outputs = 0;
+        if self.depth { outputs |= GBUFFER_DEPTH; }
+        if self.normal { outputs |= GBUFFER_NORMAL; }
+        if self.material { outputs |= GBUFFER_MATERIAL; }
+        if self.voxel { outputs |= GBUFFER_VOXEL; }
+        if self.lighting { outputs |= GBUFFER_LIGHTING; }
+        outputs
+    }
+}
+
+// Per pixel data of the first hit of the primary rays, same size as the raymarcher output
+pub struct GBuffer {
+    pub depth: (vk::Image, Allocation, vk::ImageView),
+    pub normal: (vk::Image, Allocation, vk::ImageView),
+    pub material: (vk::Image, Allocation, vk::ImageView),
+    pub voxel: (vk::Image, Allocation, vk::ImageView),
+    pub illumination: (vk::Image, Allocation, vk::ImageView),
+    pub albedo: (vk::Image, Allocation, vk::ImageView),
+    pub motion: (vk::Image, Allocation, vk::ImageView),
+}
+
+impl GBuffer {
+    pub fn images(&self) -> [vk::Image; 7] {
+        [self.depth.0, self.normal.0, self.material.0, self.voxel.0, self.illumination.0, self.albedo.0, self.motion.0]
+    }
+
+    // In the order of the raymarcher bindings (5 to 11)
+    pub fn views(&self) -> [vk::ImageView; 7] {
+        [self.motion.2, self.depth.2, self.normal.2, self.material.2, self.voxel.2, self.illumination.2, self.albedo.2]
+    }
+}
+
+// The images are always allocated, even when their output is disabled, so the descriptor set stays the same
+pub unsafe fn create_gbuffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    extent: vek::Vec2<u32>,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> GBuffer {
+    GBuffer {
+        depth: image::create_storage_image(device, allocator, extent, DEPTH_FORMAT, binder, c"gbuffer depth image"),
+        normal: image::create_storage_image(device, allocator, extent, NORMAL_FORMAT, binder, c"gbuffer normal image"),
+        material: image::create_storage_image(device, allocator, extent, MATERIAL_FORMAT, binder, c"gbuffer material image"),
+        voxel: image::create_storage_image(device, allocator, extent, VOXEL_FORMAT, binder, c"gbuffer voxel image"),
+        illumination: image::create_storage_image(device, allocator, extent, LIGHTING_FORMAT, binder, c"gbuffer illumination image"),
+        albedo: image::create_storage_image(device, allocator, extent, LIGHTING_FORMAT, binder, c"gbuffer albedo image"),
+        motion: image::create_storage_image(device, allocator, extent, MOTION_FORMAT, binder, c"gbuffer motion image"),
+    }
+}
+
+pub unsafe fn destroy_gbuffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    gbuffer: GBuffer,
+) {
+    image::destroy_storage_image(device, allocator, gbuffer.depth);
+    image::destroy_storage_image(device, allocator, gbuffer.normal);
+    image::destroy_storage_image(device, allocator, gbuffer.material);
+    image::destroy_storage_image(device, allocator, gbuffer.voxel);
+    image::destroy_storage_image(device, allocator, gbuffer.illumination);
+    image::destroy_storage_image(device, allocator, gbuffer.albedo);
+    image::destroy_storage_image(device, allocator, gbuffer.motion);
+}
@@ -0,0 +1,243 @@
+use ash::vk;
+
+// Single mip storage image with a view, used for the intermediate images of the render passes
+pub unsafe fn create_storage_image(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    extent: vek::Vec2<u32>,
+    format: vk::Format,
+    binder: &Option<ash::ext::debug_utils::Device>,
+    name: &std::ffi::CStr,
+) -> (vk::Image, gpu_allocator::vulkan::Allocation, vk::ImageView) {
+    let image_create_info = vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width: extent.x.max(1),
+            height: extent.y.max(1),
+            depth: 1,
+        })
+        .format(format)
+        .image_type(vk::ImageType::TYPE_2D)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .mip_levels(1)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .usage(vk::ImageUsageFlags::STORAGE)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .array_layers(1);
+    let image = device.create_image(&image_create_info, None).unwrap();
+    let requirements = device.get_image_memory_requirements(image);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Storage Image Allocation",
+            requirements: requirements,
+            linear: false,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedImage(image),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    device
+        .bind_image_memory(image, allocation.memory(), 0)
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(image)
+            .object_name(name);
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let subresource_range = vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(1)
+        .layer_count(1);
+
+    let image_view_create_info = vk::ImageViewCreateInfo::default()
+        .components(vk::ComponentMapping::default())
+        .flags(vk::ImageViewCreateFlags::empty())
+        .format(format)
+        .image(image)
+        .subresource_range(subresource_range)
+        .view_type(vk::ImageViewType::TYPE_2D);
+    let image_view = device.create_image_view(&image_view_create_info, None).unwrap();
+
+    (image, allocation, image_view)
+}
+
+// Read-only image for sampling, with the texels (all the layers, one after the other) uploaded through a staging buffer
+// Ends up in SHADER_READ_ONLY_OPTIMAL
+pub unsafe fn create_sampled_image(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    queue_family_index: u32,
+    pool: vk::CommandPool,
+    queue: vk::Queue,
+    extent: vek::Vec2<u32>,
+    layers: u32,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    texels: &[u8],
+    binder: &Option<ash::ext::debug_utils::Device>,
+    name: &std::ffi::CStr,
+) -> (vk::Image, gpu_allocator::vulkan::Allocation, vk::ImageView) {
+
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(texels.len() as u64);
+    let staging_buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+    let requirements = device.get_buffer_memory_requirements(staging_buffer);
+
+    let mut staging_allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Staging Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(staging_buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+    device.bind_buffer_memory(staging_buffer, staging_allocation.memory(), 0).unwrap();
+    staging_allocation.mapped_slice_mut().unwrap()[..texels.len()].copy_from_slice(texels);
+
+    let image_create_info = vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width: extent.x,
+            height: extent.y,
+            depth: 1,
+        })
+        .format(format)
+        .image_type(vk::ImageType::TYPE_2D)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .mip_levels(1)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .array_layers(layers);
+    let image = device.create_image(&image_create_info, None).unwrap();
+    let requirements = device.get_image_memory_requirements(image);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Sampled Image Allocation",
+            requirements: requirements,
+            linear: false,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedImage(image),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    device
+        .bind_image_memory(image, allocation.memory(), 0)
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(image)
+            .object_name(name);
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let subresource_range = vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(1)
+        .layer_count(layers);
+
+    let cmd_buffer_create_info = vk::CommandBufferAllocateInfo::default()
+        .command_buffer_count(1)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(pool);
+    let cmd = device
+        .allocate_command_buffers(&cmd_buffer_create_info)
+        .unwrap()[0];
+
+    let cmd_buffer_begin_info =
+        vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device
+        .begin_command_buffer(cmd, &cmd_buffer_begin_info)
+        .unwrap();
+
+    let undefined_to_transfer_dst = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_access_mask(vk::AccessFlags2::NONE)
+        .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+        .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+        .src_queue_family_index(queue_family_index)
+        .dst_queue_family_index(queue_family_index)
+        .image(image)
+        .subresource_range(subresource_range);
+    let barriers = [undefined_to_transfer_dst];
+    let dep = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    let subresource_layers = vk::ImageSubresourceLayers::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(layers);
+    let region = vk::BufferImageCopy::default()
+        .buffer_offset(0)
+        .image_subresource(subresource_layers)
+        .image_extent(vk::Extent3D {
+            width: extent.x,
+            height: extent.y,
+            depth: 1,
+        });
+    device.cmd_copy_buffer_to_image(cmd, staging_buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+
+    let transfer_dst_to_shader_read = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .src_queue_family_index(queue_family_index)
+        .dst_queue_family_index(queue_family_index)
+        .image(image)
+        .subresource_range(subresource_range);
+    let barriers = [transfer_dst_to_shader_read];
+    let dep = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    device.end_command_buffer(cmd).unwrap();
+
+    let cmds = [cmd];
+    let submit_info = vk::SubmitInfo::default()
+        .command_buffers(&cmds)
+        .wait_semaphores(&[])
+        .signal_semaphores(&[])
+        .wait_dst_stage_mask(&[]);
+    let fence = device.create_fence(&Default::default(), None).unwrap();
+    device.queue_submit(queue, &[submit_info], fence).unwrap();
+    device.wait_for_fences(&[fence], false, u64::MAX).unwrap();
+    device.destroy_fence(fence, None);
+    device.free_command_buffers(pool, &[cmd]);
+
+    device.destroy_buffer(staging_buffer, None);
+    allocator.free(staging_allocation).unwrap();
+
+    let image_view_create_info = vk::ImageViewCreateInfo::default()
+        .components(vk::ComponentMapping::default())
+        .flags(vk::ImageViewCreateFlags::empty())
+        .format(format)
+        .image(image)
+        .subresource_range(subresource_range)
+        .view_type(view_type);
+    let image_view = device.create_image_view(&image_view_create_info, None).unwrap();
+
+    (image, allocation, image_view)
+}
+
+pub unsafe fn destroy_storage_image(
+    device: &ash::Device,
+    allocator: &mut gpu_allocator::vulkan::Allocator,
+    image: (vk::Image, gpu_allocator::vulkan::Allocation, vk::ImageView),
+) {
+    device.destroy_image_view(image.2, None);
+    device.destroy_image(image.0, None);
+    allocator.free(image.1).unwrap();
+}
@@ -0,0 +1,75 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+// Max number of point lights we can upload every frame. Must match the value in lighting.slang
+pub const MAX_POINT_LIGHTS: usize = 64;
+
+// Must match PointLight in voxel.slang and reference.slang
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PointLight {
+    // xyz: world position, w: radius
+    pub position: vek::Vec4<f32>,
+
+    // xyz: color, w: intensity
+    pub color: vek::Vec4<f32>,
+}
+
+impl PointLight {
+    pub fn new(position: vek::Vec3<f32>, color: vek::Vec3<f32>, radius: f32, intensity: f32) -> Self {
+        Self {
+            position: position.with_w(radius),
+            color: color.with_w(intensity),
+        }
+    }
+}
+
+// Host visible so we can write the lights directly every frame
+pub unsafe fn create_point_light_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size((size_of::<PointLight>() * MAX_POINT_LIGHTS) as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Point Light Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"point light buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Writes the lights to the mapped buffer and returns how many of them fit
+pub fn upload_point_lights(allocation: &mut Allocation, lights: &[PointLight]) -> u32 {
+    let count = lights.len().min(MAX_POINT_LIGHTS);
+    if count < lights.len() {
+        log::warn!("too many point lights ({}), only the first {} will be used", lights.len(), MAX_POINT_LIGHTS);
+    }
+
+    let raw = bytemuck::cast_slice::<PointLight, u8>(&lights[..count]);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+    count as u32
+}
@@ -14,8 +14,24 @@ mod pool;
 mod queue;
 mod surface;
 mod swapchain;
+mod image;
 mod voxel;
 mod ticker;
+mod stats;
+mod lights;
+mod time;
+mod fog;
+mod post;
+mod taa;
+mod resolution;
+mod gbuffer;
+mod reference;
+mod denoise;
+mod materials;
+mod textures;
+mod environment;
+mod clouds;
+mod view;
 
 use ash;
 use ash::vk;
@@ -55,6 +71,7 @@ struct InternalApp {
     swapchain: vk::SwapchainKHR,
     images: Vec<vk::Image>,
     rt_images: Vec<(vk::Image, Allocation)>,
+    display_images: Vec<(vk::Image, Allocation)>,
     begin_semaphore: vk::Semaphore,
     end_semaphore: vk::Semaphore,
     end_fence: vk::Fence,
@@ -71,16 +88,106 @@ struct InternalApp {
         vk::DescriptorSetLayout,
         vk::PipelineLayout,
         vk::Pipeline,
-    ); 2],
+    ); 8],
+
+    post_shader_module: vk::ShaderModule,
+    post_pipelines: [(
+        vk::DescriptorSetLayout,
+        vk::PipelineLayout,
+        vk::Pipeline,
+    ); 9],
+    histogram_buffer: (vk::Buffer, Allocation),
+    exposure_buffer: (vk::Buffer, Allocation),
+    lut_buffer: (vk::Buffer, Allocation),
+    lut_size: u32,
+    bloom_image: (vk::Image, Allocation, Vec<vk::ImageView>),
+
+    taa_shader_module: vk::ShaderModule,
+    taa_descriptor_set_layout: vk::DescriptorSetLayout,
+    taa_pipeline_layout: vk::PipelineLayout,
+    taa_pipeline: vk::Pipeline,
+    taa_history: [(vk::Image, Allocation, vk::ImageView); 2],
+    taa_index: usize,
+    taa_reset: bool,
+    taa_settings: taa::TaaSettings,
+    gbuffer: gbuffer::GBuffer,
+    gbuffer_settings: gbuffer::GBufferSettings,
+    reference_shader_module: vk::ShaderModule,
+    reference_descriptor_set_layout: vk::DescriptorSetLayout,
+    reference_pipeline_layout: vk::PipelineLayout,
+    reference_pipeline: vk::Pipeline,
+    reference_settings: reference::ReferenceSettings,
+    accumulation: reference::Accumulation,
+    accumulation_image: (vk::Image, Allocation, vk::ImageView),
+    denoise_shader_module: vk::ShaderModule,
+    denoise_pipelines: [(
+        vk::DescriptorSetLayout,
+        vk::PipelineLayout,
+        vk::Pipeline,
+    ); 4],
+    denoise_images: denoise::DenoiseImages,
+    denoise_settings: denoise::DenoiseSettings,
+    denoise_index: usize,
+    denoise_reset: bool,
+    previous_render_resolution: vek::Vec2<u32>,
+    previous_position: vek::Vec4<f32>,
+    previous_rays: vek::Mat4<f32>,
+    view_buffer: (vk::Buffer, Allocation),
+
+    dynamic_resolution: resolution::DynamicResolution,
+    timestamp_query_pool: Option<vk::QueryPool>,
+    timestamp_period: f32,
+    post_settings: post::PostSettings,
 
     descriptor_pool: vk::DescriptorPool,
     allocator: gpu_allocator::vulkan::Allocator,
     voxel_image: (vk::Image, Allocation, vk::ImageView),
     voxel_surface_index_image: (vk::Image, Allocation, vk::ImageView),
+    voxel_light_image: (vk::Image, Allocation, vk::ImageView),
     voxel_surface_buffer: (vk::Buffer, Allocation),
+    voxel_surface_capacity: u32,
+    voxel_surface_resolution: voxel::SurfaceResolution,
     voxel_surface_counter_buffer: (vk::Buffer, Allocation),
+    voxel_surface_readback_buffer: (vk::Buffer, Allocation),
+    voxel_surface_free_list_buffer: (vk::Buffer, Allocation),
+    voxel_surface_list_buffer: (vk::Buffer, Allocation),
+    voxel_dispatch_buffer: (vk::Buffer, Allocation),
+    voxel_update_settings_buffer: (vk::Buffer, Allocation),
+    point_light_buffer: (vk::Buffer, Allocation),
+    point_lights: Vec<lights::PointLight>,
+    material_buffer: (vk::Buffer, Allocation),
+    material_settings: materials::MaterialSettings,
+    block_textures: (vk::Image, Allocation, vk::ImageView),
+    block_texture_sampler: vk::Sampler,
+    environment_image: (vk::Image, Allocation, vk::ImageView),
+    environment_sampler: vk::Sampler,
+    environment_buffer: (vk::Buffer, Allocation),
+    environment_settings: environment::EnvironmentSettings,
+    environment_ambient: Option<[vek::Vec4<f32>; 6]>,
+    cloud_shadow_shader_module: vk::ShaderModule,
+    cloud_shadow_descriptor_set_layout: vk::DescriptorSetLayout,
+    cloud_shadow_pipeline_layout: vk::PipelineLayout,
+    cloud_shadow_pipeline: vk::Pipeline,
+    cloud_shadow_image: (vk::Image, Allocation, vk::ImageView),
+    cloud_buffer: (vk::Buffer, Allocation),
+    cloud_settings: clouds::CloudSettings,
+    fog_ambient_shader_module: vk::ShaderModule,
+    fog_ambient_descriptor_set_layout: vk::DescriptorSetLayout,
+    fog_ambient_pipeline_layout: vk::PipelineLayout,
+    fog_ambient_pipeline: vk::Pipeline,
+    fog_ambient_buffer: (vk::Buffer, Allocation),
+    dirty: Option<voxel::DirtyRegion>,
+    light_propagation: voxel::LightPropagation,
     ticker: ticker::Ticker,
-    sun: vek::Vec3<f32>,
+    stats: stats::Stats,
+    sun: vek::Vec4<f32>,
+    time_of_day: time::TimeOfDay,
+    fog_settings: fog::FogSettings,
+    frame: u32,
+    shadow_cache: voxel::ShadowCache,
+    shadow_settings: voxel::ShadowSettings,
+    gi_settings: voxel::GiSettings,
+    sky_visibility_settings: voxel::SkyVisibilitySettings,
 }
 
 impl InternalApp {
@@ -88,6 +195,11 @@ impl InternalApp {
         let mut assets = HashMap::<&str, Vec<u32>>::new();
         asset!("raymarcher.spv", assets);
         asset!("voxel.spv", assets);
+        asset!("post.spv", assets);
+        asset!("taa.spv", assets);
+        asset!("reference.spv", assets);
+        asset!("denoise.spv", assets);
+        asset!("cloud_shadows.spv", assets);
 
         let window = event_loop
             .create_window(Window::default_attributes())
@@ -137,6 +249,10 @@ impl InternalApp {
         let queue_family_indices = [queue_family_index];
         log::info!("created device and fetched main queue");
 
+        let timestamp_period = instance.get_physical_device_properties(physical_device).limits.timestamp_period;
+        let timestamp_valid_bits = instance.get_physical_device_queue_family_properties(physical_device)[queue_family_index as usize].timestamp_valid_bits;
+        let timestamp_query_pool = resolution::create_timestamp_query_pool(&device, timestamp_valid_bits);
+
         let debug_marker = debug_messenger.is_some().then(|| {
             let device = debug::create_debug_marker(&instance, &device);
             log::info!("created debug marker object names binder");
@@ -177,6 +293,14 @@ impl InternalApp {
         );
         log::info!("created swapchain with {} in-flight images", images.len());
 
+        // The raymarcher renders into a sub-rect of these, the TAA pass upscales to the full extent
+        let dynamic_resolution = resolution::DynamicResolution::default();
+        let max_render_resolution = resolution::max_render_resolution(vek::Vec2::new(extent.width, extent.height), dynamic_resolution.max_scale);
+        let render_extent = vk::Extent2D {
+            width: max_render_resolution.x,
+            height: max_render_resolution.y,
+        };
+
         let rt_images: Vec<(vk::Image, Allocation)> = (0..images.len())
             .into_iter()
             .map(|_| {
@@ -188,7 +312,8 @@ impl InternalApp {
                     &device,
                     &mut allocator,
                     queue_family_index,
-                    extent,
+                    render_extent,
+                    swapchain::HDR_FORMAT,
                     &debug_marker,
                     c"temporary render target image"
                 )
@@ -196,7 +321,28 @@ impl InternalApp {
             .collect();
         log::info!("created {} in-flight render texture images", images.len());
 
+        let display_images: Vec<(vk::Image, Allocation)> = (0..images.len())
+            .into_iter()
+            .map(|_| {
+                swapchain::create_temporary_target_render_image(
+                    &instance,
+                    &surface_loader,
+                    surface_khr,
+                    physical_device,
+                    &device,
+                    &mut allocator,
+                    queue_family_index,
+                    extent,
+                    swapchain::HDR_FORMAT,
+                    &debug_marker,
+                    c"tonemapped display image"
+                )
+            })
+            .collect();
+        log::info!("created {} in-flight display images", images.len());
+
         swapchain::transfer_rt_images(&device, queue_family_index, &rt_images, pool, queue);
+        swapchain::transfer_rt_images(&device, queue_family_index, &display_images, pool, queue);
         log::info!("transferred layout of render texture images");
 
         let begin_semaphore = device
@@ -209,24 +355,101 @@ impl InternalApp {
 
         let descriptor_pool = pool::create_descriptor_pool(&device);
 
+        let surface_resolution = voxel::SurfaceResolution::from_env();
+        log::info!("surface resolution: {0}x{0} texels per face", surface_resolution.edge());
+        let time_of_day = time::TimeOfDay::load();
+
         let (
             render_compute_shader_module,
             render_compute_descriptor_set_layout,
             render_compute_pipeline_layout,
             render_compute_pipeline,
-        ) = pipeline::create_render_compute_pipeline(&*assets["raymarcher.spv"], &device);
+        ) = pipeline::create_render_compute_pipeline(&*assets["raymarcher.spv"], &device, surface_resolution.edge());
         log::info!("created render compute pipeline");
 
         let (
             voxel_compute_shader_module,
             voxel_compute_pipelines,
-        ) = pipeline::create_compute_voxel_pipelines(&*assets["voxel.spv"], &device);
+        ) = pipeline::create_compute_voxel_pipelines(&*assets["voxel.spv"], &device, surface_resolution.edge());
         log::info!("created voxel compute pipeline");
 
+        let (
+            post_shader_module,
+            post_pipelines,
+        ) = pipeline::create_post_pipelines(&*assets["post.spv"], &device);
+        log::info!("created post processing pipelines");
+
+        let (
+            taa_shader_module,
+            taa_descriptor_set_layout,
+            taa_pipeline_layout,
+            taa_pipeline,
+        ) = pipeline::create_taa_pipeline(&*assets["taa.spv"], &device);
+        log::info!("created taa pipeline");
+
+        let (
+            reference_shader_module,
+            reference_descriptor_set_layout,
+            reference_pipeline_layout,
+            reference_pipeline,
+        ) = pipeline::create_reference_pipeline(&*assets["reference.spv"], &device);
+        log::info!("created reference pipeline");
+
+        let (
+            denoise_shader_module,
+            denoise_pipelines,
+        ) = pipeline::create_denoise_pipelines(&*assets["denoise.spv"], &device);
+        log::info!("created denoise pipelines");
+
+        let (
+            cloud_shadow_shader_module,
+            cloud_shadow_descriptor_set_layout,
+            cloud_shadow_pipeline_layout,
+            cloud_shadow_pipeline,
+        ) = pipeline::create_cloud_shadow_pipeline(&*assets["cloud_shadows.spv"], &device);
+        log::info!("created cloud shadow pipeline");
+
+        let (
+            fog_ambient_shader_module,
+            fog_ambient_descriptor_set_layout,
+            fog_ambient_pipeline_layout,
+            fog_ambient_pipeline,
+        ) = pipeline::create_fog_ambient_pipeline(&*assets["fog_ambient.spv"], &device);
+        log::info!("created fog ambient pipeline");
+
         let voxel_image = voxel::create_voxel_image(&device, &mut allocator, vk::Format::R8_UINT, vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST, &debug_marker, c"voxel image");
-        let voxel_surface_index_image = voxel::create_voxel_image(&device, &mut allocator, vk::Format::R32_UINT, vk::ImageUsageFlags::STORAGE, &debug_marker, c"voxel image indices");
-        let voxel_surface_buffer = voxel::create_voxel_surface_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_surface_index_image = voxel::create_voxel_image(&device, &mut allocator, voxel::SURFACE_INDEX_FORMAT, vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST, &debug_marker, c"voxel image indices");
+        let voxel_light_image = voxel::create_voxel_image(&device, &mut allocator, vk::Format::R8_UINT, vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST, &debug_marker, c"voxel light image");
+        let voxel_surface_buffer = voxel::create_voxel_surface_buffer(&device, &mut allocator, &debug_marker, surface_resolution, voxel::INITIAL_SURFACE_CAPACITY);
         let voxel_surface_counter_buffer = voxel::create_voxel_counter_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_surface_readback_buffer = voxel::create_voxel_counter_readback_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_surface_free_list_buffer = voxel::create_voxel_free_list_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_surface_list_buffer = voxel::create_voxel_surface_list_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_dispatch_buffer = voxel::create_voxel_dispatch_buffer(&device, &mut allocator, &debug_marker);
+        let voxel_update_settings_buffer = voxel::create_voxel_update_settings_buffer(&device, &mut allocator, &debug_marker);
+        let point_light_buffer = lights::create_point_light_buffer(&device, &mut allocator, &debug_marker);
+        let material_buffer = materials::create_material_buffer(&device, &mut allocator, &debug_marker);
+        let block_textures = textures::create_block_texture_array(&device, &mut allocator, queue_family_index, pool, queue, &textures::BlockTextures::load(), &debug_marker);
+        let block_texture_sampler = textures::create_block_texture_sampler(&device);
+        let max_image_extent = instance.get_physical_device_properties(physical_device).limits.max_image_dimension2_d;
+        let environment_map = environment::EnvironmentMap::load(max_image_extent);
+        let environment_ambient = environment_map.as_ref().map(|map| map.ambient());
+        let environment_image = environment::create_environment_image(&device, &mut allocator, queue_family_index, pool, queue, environment_map.as_ref(), &debug_marker);
+        let environment_sampler = environment::create_environment_sampler(&device);
+        let environment_buffer = environment::create_environment_buffer(&device, &mut allocator, &debug_marker);
+        let cloud_shadow_image = clouds::create_cloud_shadow_image(&device, &mut allocator, &debug_marker);
+        let cloud_buffer = clouds::create_cloud_buffer(&device, &mut allocator, &debug_marker);
+        let view_buffer = view::create_view_buffer(&device, &mut allocator, &debug_marker);
+        let fog_ambient_buffer = fog::create_fog_ambient_buffer(&device, &mut allocator, &debug_marker);
+        let histogram_buffer = post::create_histogram_buffer(&device, &mut allocator, &debug_marker);
+        let exposure_buffer = post::create_exposure_buffer(&device, &mut allocator, &debug_marker);
+        let lut = post::ColorGradingLut::load();
+        let lut_buffer = post::create_lut_buffer(&device, &mut allocator, &debug_marker, &lut);
+        let bloom_image = post::create_bloom_image(&device, &mut allocator, extent, &debug_marker);
+        let taa_history = taa::create_history_images(&device, &mut allocator, vek::Vec2::new(extent.width, extent.height), &debug_marker);
+        let accumulation_image = reference::create_accumulation_image(&device, &mut allocator, vek::Vec2::new(extent.width, extent.height), &debug_marker);
+        let gbuffer = gbuffer::create_gbuffer(&device, &mut allocator, vek::Vec2::new(render_extent.width, render_extent.height), &debug_marker);
+        let denoise_images = denoise::create_denoise_images(&device, &mut allocator, vek::Vec2::new(render_extent.width, render_extent.height), &debug_marker);
 
         voxel::generate_voxel_image(
             &device,
@@ -238,9 +461,11 @@ impl InternalApp {
             voxel_image.2,
             voxel_surface_index_image.0,
             voxel_surface_index_image.2,
-            voxel_compute_pipelines[0].0,
-            voxel_compute_pipelines[0].1,
-            voxel_compute_pipelines[0].2,
+            voxel_light_image.0,
+            voxel_surface_counter_buffer.0,
+            voxel_compute_pipelines[pipeline::VOXEL_GENERATE].0,
+            voxel_compute_pipelines[pipeline::VOXEL_GENERATE].1,
+            voxel_compute_pipelines[pipeline::VOXEL_GENERATE].2,
             
         );
 
@@ -272,22 +497,105 @@ impl InternalApp {
             render_compute_pipeline,
             voxel_compute_shader_module,
             voxel_compute_pipelines,
+            post_shader_module,
+            post_pipelines,
+            histogram_buffer,
+            exposure_buffer,
+            lut_buffer,
+            lut_size: lut.size,
+            bloom_image,
+            taa_shader_module,
+            taa_descriptor_set_layout,
+            taa_pipeline_layout,
+            taa_pipeline,
+            taa_history,
+            taa_index: 0,
+            taa_reset: true,
+            taa_settings: taa::TaaSettings::default(),
+            gbuffer,
+            gbuffer_settings: gbuffer::GBufferSettings::default(),
+            reference_shader_module,
+            reference_descriptor_set_layout,
+            reference_pipeline_layout,
+            reference_pipeline,
+            reference_settings: reference::ReferenceSettings::default(),
+            accumulation: reference::Accumulation::default(),
+            accumulation_image,
+            denoise_shader_module,
+            denoise_pipelines,
+            denoise_images,
+            denoise_settings: denoise::DenoiseSettings::default(),
+            denoise_index: 0,
+            denoise_reset: true,
+            previous_render_resolution: vek::Vec2::zero(),
+            previous_position: vek::Vec4::zero(),
+            previous_rays: vek::Mat4::identity(),
+            view_buffer,
+            dynamic_resolution,
+            timestamp_query_pool,
+            timestamp_period,
+            post_settings: post::PostSettings::default(),
             descriptor_pool,
             allocator,
             voxel_image,
             rt_images,
+            display_images,
             ticker: ticker::Ticker { ticks_per_second: 120f32, accumulator: 0f32, count: 0 },
             voxel_surface_buffer,
+            voxel_surface_capacity: voxel::INITIAL_SURFACE_CAPACITY,
+            voxel_surface_resolution: surface_resolution,
             voxel_surface_index_image,
+            voxel_light_image,
             voxel_surface_counter_buffer,
-            sun: vek::Vec3::unit_y() + vek::Vec3::unit_x(),
+            voxel_surface_readback_buffer,
+            voxel_surface_free_list_buffer,
+            voxel_surface_list_buffer,
+            voxel_dispatch_buffer,
+            voxel_update_settings_buffer,
+            point_light_buffer,
+            point_lights: Vec::new(),
+            material_buffer,
+            material_settings: materials::MaterialSettings::default(),
+            block_textures,
+            block_texture_sampler,
+            environment_image,
+            environment_sampler,
+            environment_buffer,
+            environment_settings: environment::EnvironmentSettings::default(),
+            environment_ambient,
+            cloud_shadow_shader_module,
+            cloud_shadow_descriptor_set_layout,
+            cloud_shadow_pipeline_layout,
+            cloud_shadow_pipeline,
+            cloud_shadow_image,
+            cloud_buffer,
+            cloud_settings: clouds::CloudSettings::default(),
+            fog_ambient_shader_module,
+            fog_ambient_descriptor_set_layout,
+            fog_ambient_pipeline_layout,
+            fog_ambient_pipeline,
+            fog_ambient_buffer,
+            dirty: Some(voxel::DirtyRegion::full()),
+            light_propagation: voxel::LightPropagation::new(),
+            stats: Default::default(),
+            sun: time_of_day.light(),
+            time_of_day,
+            fog_settings: fog::FogSettings::default(),
+            frame: 0,
+            shadow_cache: voxel::ShadowCache::new(time_of_day.light().xyz()),
+            shadow_settings: voxel::ShadowSettings::default(),
+            gi_settings: voxel::GiSettings::default(),
+            sky_visibility_settings: voxel::SkyVisibilitySettings::default(),
         }
     }
 
-    pub unsafe fn click(&mut self, add: bool) {
+    pub unsafe fn click(&mut self, voxel: voxel::Voxel) {
         let forward = vek::Mat4::from(self.movement.rotation).mul_direction(-vek::Vec3::unit_z()).with_w(0.0f32);
         let position = (self.movement.position + forward * 2.0).map(|x| x as u32);
 
+        if position.iter().any(|&x| x >= voxel::SIZE) {
+            return;
+        }
 
         voxel::update_voxel(
             &self.device,
@@ -295,14 +603,15 @@ impl InternalApp {
             self.queue,
             self.pool,
             self.voxel_image.0,
-            voxel::Voxel {
-                active: true,
-                reflective: false,
-                refractive: add,
-                placed: true,
-            }.into_raw(),
+            voxel.into_raw(),
             position,
-        )
+        );
+
+        // Surface data of the voxel and its neighbours gets recomputed next tick
+        let region = voxel::DirtyRegion::around(position);
+        self.dirty = Some(self.dirty.map_or(region, |dirty| dirty.merge(region)));
+        self.light_propagation.mark(region);
+        self.accumulation.reset();
     }
 
     pub unsafe fn resize(&mut self, width: u32, height: u32) {
@@ -311,7 +620,7 @@ impl InternalApp {
         self.swapchain_loader
             .destroy_swapchain(self.swapchain, None);
 
-        for (image, allocation) in self.rt_images.drain(..) {
+        for (image, allocation) in self.rt_images.drain(..).chain(self.display_images.drain(..)) {
             self.device.destroy_image(image, None);
             self.allocator.free(allocation).unwrap();
         }
@@ -332,6 +641,12 @@ impl InternalApp {
         self.swapchain_format = swapchain_format;
         self.swapchain = swapchain;
 
+        let max_render_resolution = resolution::max_render_resolution(vek::Vec2::new(width, height), self.dynamic_resolution.max_scale);
+        let render_extent = vk::Extent2D {
+            width: max_render_resolution.x,
+            height: max_render_resolution.y,
+        };
+
         let rt_images: Vec<(vk::Image, Allocation)> = (0..self.images.len())
             .into_iter()
             .map(|_| {
@@ -343,12 +658,31 @@ impl InternalApp {
                     &self.device,
                     &mut self.allocator,
                     self.queue_family_index,
-                    extent,
+                    render_extent,
+                    swapchain::HDR_FORMAT,
                     &self.debug_marker,
                     c"temporary render target image"
                 )
             })
             .collect();
+        let display_images: Vec<(vk::Image, Allocation)> = (0..self.images.len())
+            .into_iter()
+            .map(|_| {
+                swapchain::create_temporary_target_render_image(
+                    &self.instance,
+                    &self.surface_loader,
+                    self.surface_khr,
+                    self.physical_device,
+                    &self.device,
+                    &mut self.allocator,
+                    self.queue_family_index,
+                    extent,
+                    swapchain::HDR_FORMAT,
+                    &self.debug_marker,
+                    c"tonemapped display image"
+                )
+            })
+            .collect();
         swapchain::transfer_rt_images(
             &self.device,
             self.queue_family_index,
@@ -356,7 +690,39 @@ impl InternalApp {
             self.pool,
             self.queue,
         );
+        swapchain::transfer_rt_images(
+            &self.device,
+            self.queue_family_index,
+            &display_images,
+            self.pool,
+            self.queue,
+        );
         self.rt_images = rt_images;
+        self.display_images = display_images;
+
+        let bloom_image = post::create_bloom_image(&self.device, &mut self.allocator, extent, &self.debug_marker);
+        let old = std::mem::replace(&mut self.bloom_image, bloom_image);
+        post::destroy_bloom_image(&self.device, &mut self.allocator, old);
+
+        // The history does not mean anything at a different resolution anyways
+        let taa_history = taa::create_history_images(&self.device, &mut self.allocator, vek::Vec2::new(extent.width, extent.height), &self.debug_marker);
+        for history in std::mem::replace(&mut self.taa_history, taa_history) {
+            image::destroy_storage_image(&self.device, &mut self.allocator, history);
+        }
+
+        let gbuffer = gbuffer::create_gbuffer(&self.device, &mut self.allocator, vek::Vec2::new(render_extent.width, render_extent.height), &self.debug_marker);
+        let old = std::mem::replace(&mut self.gbuffer, gbuffer);
+        gbuffer::destroy_gbuffer(&self.device, &mut self.allocator, old);
+
+        let accumulation_image = reference::create_accumulation_image(&self.device, &mut self.allocator, vek::Vec2::new(extent.width, extent.height), &self.debug_marker);
+        let old = std::mem::replace(&mut self.accumulation_image, accumulation_image);
+        image::destroy_storage_image(&self.device, &mut self.allocator, old);
+
+        let denoise_images = denoise::create_denoise_images(&self.device, &mut self.allocator, vek::Vec2::new(render_extent.width, render_extent.height), &self.debug_marker);
+        let old = std::mem::replace(&mut self.denoise_images, denoise_images);
+        denoise::destroy_denoise_images(&self.device, &mut self.allocator, old);
+        self.taa_reset = true;
+        self.denoise_reset = true;
     }
 
     pub unsafe fn render(&mut self, delta: f32, elapsed: f32) {
@@ -373,6 +739,7 @@ impl InternalApp {
             .unwrap();
         let dst_image = self.images[index as usize];
         let (src_image, _) = self.rt_images[index as usize];
+        let (display_image, _) = self.display_images[index as usize];
 
         let cmd_buffer_create_info = vk::CommandBufferAllocateInfo::default()
             .command_buffer_count(1)
@@ -389,33 +756,71 @@ impl InternalApp {
             .begin_command_buffer(cmd, &cmd_buffer_begin_info)
             .unwrap();
 
-        //self.sun = vek::Vec3::new(1f32, 0.3f32,0.5f32).normalized();
-        self.sun = vek::Vec3::new((elapsed * 0.1f32).sin(), 0.3, (elapsed * 0.1f32).cos()).normalized();
+        if let Some(query_pool) = self.timestamp_query_pool {
+            resolution::write_start_timestamp(&self.device, cmd, query_pool);
+        }
+
+        // The sun only moves on ticks so it doesn't depend on the frame rate
+        let ticked = self.ticker.update(delta);
+        if ticked {
+            // The reference mode can only accumulate a still scene, so the day cycle and the clouds wait for it
+            if !self.reference_settings.enabled {
+                self.time_of_day.tick(1f32 / self.ticker.ticks_per_second);
+                self.cloud_settings.tick(1f32 / self.ticker.ticks_per_second);
+            }
+
+            let last = self.sun.xyz();
+            self.sun = self.time_of_day.light();
+            self.shadow_cache.track_sun(self.sun.xyz(), last);
+        }
 
         let push_constants = PushConstants2 {
-            forward: vek::Mat4::from(self.movement.rotation).mul_direction(-vek::Vec3::unit_z()).with_w(0.0f32),
+            view_proj: self.movement.proj_matrix * self.movement.view_matrix,
             position: self.movement.position.with_w(0.0f32),
-            sun: self.sun.normalized().with_w(0f32),
+            sun: self.sun,
             tick: self.ticker.count,
 
             // FIXME: assumes we are running the shadow calc for every frame...
             delta: delta.max(1f32 / self.ticker.ticks_per_second),
+            sun_epoch: self.shadow_cache.epoch,
+            resolve: self.shadow_cache.resolve as u32,
+            light_count: lights::upload_point_lights(&mut self.point_light_buffer.1, &self.point_lights),
         };
 
-        let desc_temp = self.ticker.update(delta).then(|| voxel::update_voxel_thingies(
+        let update_settings = voxel::UpdateSettings::new(&self.shadow_settings, &self.gi_settings, &self.sky_visibility_settings);
+        voxel::upload_update_settings(&mut self.voxel_update_settings_buffer.1, &update_settings);
+
+        let desc_temp = ticked.then(|| voxel::update_voxel_thingies(
             &self.device,
             cmd,
             self.descriptor_pool,
             self.queue_family_index,
             self.voxel_surface_buffer.0,
             self.voxel_surface_counter_buffer.0,
+            self.voxel_surface_readback_buffer.0,
+            self.voxel_surface_free_list_buffer.0,
+            self.voxel_surface_list_buffer.0,
+            self.voxel_dispatch_buffer.0,
+            self.point_light_buffer.0,
+            self.voxel_update_settings_buffer.0,
             self.voxel_image.0,
             self.voxel_image.2,
             self.voxel_surface_index_image.0,
             self.voxel_surface_index_image.2,
-            self.voxel_compute_pipelines[1].0,
-            self.voxel_compute_pipelines[1].1,
-            self.voxel_compute_pipelines[1].2,
+            self.voxel_light_image.0,
+            self.voxel_light_image.2,
+            self.voxel_compute_pipelines[pipeline::VOXEL_UPDATE].0,
+            self.voxel_compute_pipelines[pipeline::VOXEL_UPDATE].1,
+            self.voxel_compute_pipelines[pipeline::VOXEL_UPDATE].2,
+            (self.voxel_compute_pipelines[pipeline::VOXEL_RELEASE].1, self.voxel_compute_pipelines[pipeline::VOXEL_RELEASE].2),
+            (self.voxel_compute_pipelines[pipeline::VOXEL_ALLOCATE].1, self.voxel_compute_pipelines[pipeline::VOXEL_ALLOCATE].2),
+            (self.voxel_compute_pipelines[pipeline::VOXEL_INVALIDATE].1, self.voxel_compute_pipelines[pipeline::VOXEL_INVALIDATE].2),
+            (self.voxel_compute_pipelines[pipeline::VOXEL_COMPACT].1, self.voxel_compute_pipelines[pipeline::VOXEL_COMPACT].2),
+            (self.voxel_compute_pipelines[pipeline::VOXEL_PREPARE].1, self.voxel_compute_pipelines[pipeline::VOXEL_PREPARE].2),
+            (self.voxel_compute_pipelines[pipeline::VOXEL_PROPAGATE].1, self.voxel_compute_pipelines[pipeline::VOXEL_PROPAGATE].2),
+            self.dirty.take(),
+            self.light_propagation.take(),
+            self.voxel_surface_capacity,
             push_constants
         ));
 
@@ -427,11 +832,19 @@ impl InternalApp {
         let src_image_view_create_info = vk::ImageViewCreateInfo::default()
             .components(vk::ComponentMapping::default())
             .flags(vk::ImageViewCreateFlags::empty())
-            .format(self.swapchain_format)
+            .format(swapchain::HDR_FORMAT)
             .image(src_image)
             .subresource_range(subresource_range)
             .view_type(vk::ImageViewType::TYPE_2D);
 
+        let display_image_view_create_info = vk::ImageViewCreateInfo::default()
+            .components(vk::ComponentMapping::default())
+            .flags(vk::ImageViewCreateFlags::empty())
+            .format(swapchain::HDR_FORMAT)
+            .image(display_image)
+            .subresource_range(subresource_range)
+            .view_type(vk::ImageViewType::TYPE_2D);
+
         let dst_image_view_create_info = vk::ImageViewCreateInfo::default()
             .components(vk::ComponentMapping::default())
             .flags(vk::ImageViewCreateFlags::empty())
@@ -448,6 +861,10 @@ impl InternalApp {
             .device
             .create_image_view(&dst_image_view_create_info, None)
             .unwrap();
+        let display_image_view = self
+            .device
+            .create_image_view(&display_image_view_create_info, None)
+            .unwrap();
 
         let dst_undefined_to_blit_dst_layout_transition = vk::ImageMemoryBarrier2::default()
             .old_layout(vk::ImageLayout::UNDEFINED)
@@ -464,6 +881,46 @@ impl InternalApp {
         let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
         self.device.cmd_pipeline_barrier2(cmd, &dep);
 
+        if self.taa_reset {
+            let mut images = self.gbuffer.images().to_vec();
+            images.extend([self.taa_history[0].0, self.taa_history[1].0, self.accumulation_image.0]);
+            images.extend(self.denoise_images.images());
+            images.push(self.cloud_shadow_image.0);
+            taa::transition_images(&self.device, cmd, &images);
+
+            // Whatever was in the accumulation image is gone now
+            self.accumulation.reset();
+        }
+
+        // Both the raymarcher and the reference mode read the cloud shadows
+        let clouds = self.cloud_settings.uniform(self.movement.position);
+        clouds::upload_clouds(&mut self.cloud_buffer.1, &clouds);
+        let cloud_shadow_descriptor_set = clouds::render_cloud_shadows(
+            &self.device,
+            cmd,
+            self.descriptor_pool,
+            self.cloud_shadow_image.2,
+            self.cloud_buffer.0,
+            self.cloud_shadow_descriptor_set_layout,
+            self.cloud_shadow_pipeline_layout,
+            self.cloud_shadow_pipeline,
+            pipeline::PushConstants8 { sun: self.sun },
+        );
+
+        let environment = self.environment_settings.uniform(self.environment_ambient.as_ref());
+        environment::upload_environment(&mut self.environment_buffer.1, &environment);
+        let fog_ambient_descriptor_set = fog::render_fog_ambient(
+            &self.device,
+            cmd,
+            self.descriptor_pool,
+            self.fog_ambient_buffer.0,
+            self.environment_buffer.0,
+            self.fog_ambient_descriptor_set_layout,
+            self.fog_ambient_pipeline_layout,
+            self.fog_ambient_pipeline,
+            pipeline::PushConstants8 { sun: self.sun },
+        );
+
         /*
         self.device.cmd_clear_color_image(cmd, dst_image, vk::ImageLayout::GENERAL, &vk::ClearColorValue {
             float32: [elapsed.sin() * 0.5 + 0.5; 4]
@@ -492,6 +949,10 @@ impl InternalApp {
             .image_view(self.voxel_surface_index_image.2)
             .image_layout(vk::ImageLayout::GENERAL)
             .sampler(vk::Sampler::null());
+        let descriptor_voxel_light_image_info = vk::DescriptorImageInfo::default()
+            .image_view(self.voxel_light_image.2)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null());
         let descriptor_voxel_buffer_info = vk::DescriptorBufferInfo::default()
             .buffer(self.voxel_surface_buffer.0)
             .offset(0)
@@ -499,8 +960,22 @@ impl InternalApp {
         let descriptor_rt_image_infos = [descriptor_rt_image_info];
         let descriptor_voxel_image_infos = [descriptor_voxel_image_info];
         let descriptor_voxel_surface_index_image_infos = [descriptor_voxel_surface_index_image_info];
+        let descriptor_voxel_light_image_infos = [descriptor_voxel_light_image_info];
+        let descriptor_gbuffer_image_infos = self.gbuffer.views().map(|view| {
+            [vk::DescriptorImageInfo::default()
+                .image_view(view)
+                .image_layout(vk::ImageLayout::GENERAL)
+                .sampler(vk::Sampler::null())]
+        });
         let descriptor_voxel_buffer_infos = [descriptor_voxel_buffer_info];
 
+        // Frames are synchronous so nothing is reading the materials right now
+        materials::upload_materials(&mut self.material_buffer.1, &self.material_settings);
+        let descriptor_material_buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(self.material_buffer.0)
+            .offset(0)
+            .range(u64::MAX)];
+
         let descriptor_write_1 = vk::WriteDescriptorSet::default()
             .descriptor_count(1)
             .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
@@ -525,9 +1000,115 @@ impl InternalApp {
             .dst_binding(3)
             .dst_set(descriptor_set)
             .image_info(&descriptor_voxel_surface_index_image_infos);
+        let descriptor_write_5 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(4)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_voxel_light_image_infos);
+
+        // Motion vectors and the rest of the G-buffer go in bindings 5 to 11
+        let descriptor_gbuffer_writes = descriptor_gbuffer_image_infos.iter().enumerate().map(|(i, infos)| {
+            vk::WriteDescriptorSet::default()
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .dst_binding(5 + i as u32)
+                .dst_set(descriptor_set)
+                .image_info(infos)
+        });
+
+        let descriptor_write_6 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(12)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_material_buffer_infos);
+
+        let descriptor_block_texture_infos = [vk::DescriptorImageInfo::default()
+            .image_view(self.block_textures.2)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(self.block_texture_sampler)];
+        let descriptor_write_7 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .dst_binding(13)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_block_texture_infos);
+
+        let descriptor_environment_map_infos = [vk::DescriptorImageInfo::default()
+            .image_view(self.environment_image.2)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(self.environment_sampler)];
+        let descriptor_environment_buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(self.environment_buffer.0)
+            .offset(0)
+            .range(u64::MAX)];
+        let descriptor_write_8 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .dst_binding(14)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_environment_map_infos);
+        let descriptor_write_9 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(15)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_environment_buffer_infos);
+
+        let descriptor_cloud_buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(self.cloud_buffer.0)
+            .offset(0)
+            .range(u64::MAX)];
+        let descriptor_cloud_shadow_image_infos = [vk::DescriptorImageInfo::default()
+            .image_view(self.cloud_shadow_image.2)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null())];
+        let descriptor_write_10 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(16)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_cloud_buffer_infos);
+        let descriptor_write_11 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(17)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_cloud_shadow_image_infos);
+
+        let view = view::View {
+            view_proj: self.movement.proj_matrix * self.movement.view_matrix,
+            previous_rays: self.previous_rays,
+            previous_position: self.previous_position,
+        };
+        view::upload_view(&mut self.view_buffer.1, &view);
+        let descriptor_view_buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(self.view_buffer.0)
+            .offset(0)
+            .range(u64::MAX)];
+        let descriptor_write_12 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(18)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_view_buffer_infos);
+        let descriptor_fog_ambient_buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(self.fog_ambient_buffer.0)
+            .offset(0)
+            .range(u64::MAX)];
+        let descriptor_write_13 = vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(19)
+            .dst_set(descriptor_set)
+            .buffer_info(&descriptor_fog_ambient_buffer_infos);
 
+        let mut descriptor_writes = vec![descriptor_write_1, descriptor_write_2, descriptor_write_3, descriptor_write_4, descriptor_write_5];
+        descriptor_writes.extend(descriptor_gbuffer_writes);
+        descriptor_writes.extend([descriptor_write_6, descriptor_write_7, descriptor_write_8, descriptor_write_9, descriptor_write_10, descriptor_write_11, descriptor_write_12, descriptor_write_13]);
         self.device
-            .update_descriptor_sets(&[descriptor_write_1, descriptor_write_2, descriptor_write_3, descriptor_write_4], &[]);
+            .update_descriptor_sets(&descriptor_writes, &[]);
 
         self.device.cmd_bind_descriptor_sets(
             cmd,
@@ -543,22 +1124,34 @@ impl InternalApp {
             self.render_compute_pipeline,
         );
 
-        let size = self.window.inner_size();
-        let size = vek::Vec2::<u32>::new(size.width, size.height)
-            .map(|val| val / swapchain::SCALING_FACTOR);
+        let output_resolution = vek::Vec2::new(self.window.inner_size().width, self.window.inner_size().height);
+        let size = self.dynamic_resolution.render_resolution(output_resolution);
 
         let width_group_size = (size.x as f32 / 32f32).ceil() as u32;
         let height_group_size = (size.y as f32 / 32f32).ceil() as u32;
 
+        let render_resolution = size;
         let size = size.map(|x| x as f32);
+        let jitter = self.taa_settings.jitter(self.frame);
+        let view_proj = self.movement.proj_matrix * self.movement.view_matrix;
 
         let push_constants = pipeline::PushConstants {
             screen_resolution: size,
-            _padding: Default::default(),
-            matrix: self.movement.proj_matrix * self.movement.view_matrix,
+            gi_strength: self.gi_settings.strength(),
+            sky_visibility_strength: self.sky_visibility_settings.strength(),
             position: self.movement.position.with_w(0f32),
-            sun: self.sun.normalized().with_w(0f32),
+            sun: self.sun,
+            fog_color: self.fog_settings.color(),
+            fog_height_falloff: self.fog_settings.height_falloff,
+            fog_anisotropy: self.fog_settings.anisotropy,
+            fog_scattering: self.fog_settings.scattering,
+            fog_steps: self.fog_settings.steps(),
+            frame: self.frame,
+            gbuffer_outputs: self.gbuffer_settings.outputs() | self.denoise_settings.gbuffer_outputs(),
+            jitter,
+            max_bounces: self.material_settings.bounces,
         };
+        self.frame = self.frame.wrapping_add(1);
 
         let raw = bytemuck::bytes_of(&push_constants);
 
@@ -569,8 +1162,104 @@ impl InternalApp {
             0,
             raw,
         );
-        self.device
-            .cmd_dispatch(cmd, width_group_size, height_group_size, 1);
+
+        // The reference mode traces everything itself at the full resolution
+        if !self.reference_settings.enabled {
+            self.device
+                .cmd_dispatch(cmd, width_group_size, height_group_size, 1);
+        }
+
+        // Filter the noisy lighting of the raymarcher before it gets accumulated by the TAA pass
+        let denoise_descriptor_set = (self.denoise_settings.enabled && !self.reference_settings.enabled).then(|| {
+            denoise::denoise(
+                &self.device,
+                cmd,
+                self.descriptor_pool,
+                &self.gbuffer,
+                &self.denoise_images,
+                src_image_view,
+                &self.denoise_pipelines,
+                &self.denoise_settings,
+                self.denoise_settings.push_constants(render_resolution, self.previous_render_resolution, self.denoise_index, self.denoise_reset || self.taa_reset),
+            )
+        });
+
+        // Upscale the jittered output to the full resolution using the history of the last frames
+        // or accumulate one more path traced sample in the reference mode
+        let (_, _, resolved_image_view) = self.taa_history[self.taa_index];
+        let light_count = self.point_lights.len().min(lights::MAX_POINT_LIGHTS) as u32;
+        if self.reference_settings.enabled {
+            self.accumulation.update(view_proj, self.movement.position.with_w(0f32), self.sun, &clouds, &self.point_lights[..light_count as usize]);
+        }
+
+        let accumulate = self.accumulation.samples < self.reference_settings.max_samples;
+        let resolve_descriptor_set = if self.reference_settings.enabled {
+            reference::trace(
+                &self.device,
+                cmd,
+                self.descriptor_pool,
+                resolved_image_view,
+                self.voxel_image.2,
+                self.accumulation_image.2,
+                self.point_light_buffer.0,
+                self.material_buffer.0,
+                self.environment_buffer.0,
+                self.cloud_buffer.0,
+                self.cloud_shadow_image.2,
+                (self.block_textures.2, self.block_texture_sampler),
+                (self.environment_image.2, self.environment_sampler),
+                self.reference_descriptor_set_layout,
+                self.reference_pipeline_layout,
+                self.reference_pipeline,
+                pipeline::PushConstants6 {
+                    resolution: output_resolution,
+                    samples: self.accumulation.samples,
+                    bounces: self.reference_settings.bounces,
+                    matrix: view_proj,
+                    position: self.movement.position.with_w(0f32),
+                    sun: self.sun,
+                    light_count,
+                    accumulate: accumulate as u32,
+                },
+            )
+        } else {
+            taa::resolve(
+                &self.device,
+                cmd,
+                self.descriptor_pool,
+                src_image_view,
+                self.gbuffer.motion.2,
+                self.taa_history[1 - self.taa_index].2,
+                resolved_image_view,
+                self.taa_descriptor_set_layout,
+                self.taa_pipeline_layout,
+                self.taa_pipeline,
+                pipeline::PushConstants5 {
+                    input_resolution: render_resolution,
+                    output_resolution,
+                    jitter,
+                    blend: self.taa_settings.blend(),
+                    reset: self.taa_reset as u32,
+                },
+            )
+        };
+
+        // Exposure, bloom and tonemapping from the HDR render target into the display image, then the LDR effects
+        let post_descriptor_set = post::post_process(
+            &self.device,
+            cmd,
+            self.descriptor_pool,
+            resolved_image_view,
+            display_image_view,
+            self.histogram_buffer.0,
+            self.exposure_buffer.0,
+            self.lut_buffer.0,
+            self.bloom_image.0,
+            &self.bloom_image.2,
+            &self.post_pipelines,
+            &self.post_settings,
+            self.post_settings.push_constants(output_resolution, delta, self.lut_size, self.frame),
+        );
 
         let src_shader_write_to_transfer_src = vk::ImageMemoryBarrier2::default()
             .old_layout(vk::ImageLayout::GENERAL)
@@ -581,7 +1270,7 @@ impl InternalApp {
             .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
             .src_queue_family_index(self.queue_family_index)
             .dst_queue_family_index(self.queue_family_index)
-            .image(src_image)
+            .image(display_image)
             .subresource_range(subresource_range);
         let image_memory_barriers = [src_shader_write_to_transfer_src];
         let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
@@ -589,8 +1278,8 @@ impl InternalApp {
 
         let origin_offset = vk::Offset3D::default();
         let src_extent_offset = vk::Offset3D::default()
-            .x(self.window.inner_size().width as i32 / swapchain::SCALING_FACTOR as i32)
-            .y(self.window.inner_size().height as i32 / swapchain::SCALING_FACTOR as i32)
+            .x(self.window.inner_size().width as i32)
+            .y(self.window.inner_size().height as i32)
             .z(1);
         let dst_extent_offset = vk::Offset3D::default()
             .x(self.window.inner_size().width as i32)
@@ -614,7 +1303,7 @@ impl InternalApp {
         let regions = [image_blit];
         self.device.cmd_blit_image(
             cmd,
-            src_image,
+            display_image,
             vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
             dst_image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
@@ -631,7 +1320,7 @@ impl InternalApp {
             .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
             .src_queue_family_index(self.queue_family_index)
             .dst_queue_family_index(self.queue_family_index)
-            .image(src_image)
+            .image(display_image)
             .subresource_range(subresource_range);
 
         let blit_dst_to_present_layout_transition = vk::ImageMemoryBarrier2::default()
@@ -650,6 +1339,10 @@ impl InternalApp {
         let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
         self.device.cmd_pipeline_barrier2(cmd, &dep);
 
+        if let Some(query_pool) = self.timestamp_query_pool {
+            resolution::write_end_timestamp(&self.device, cmd, query_pool);
+        }
+
         self.device.end_command_buffer(cmd).unwrap();
 
         let cmds = [cmd];
@@ -683,16 +1376,103 @@ impl InternalApp {
 
         self.device.destroy_image_view(src_image_view, None);
         self.device.destroy_image_view(dst_image_view, None);
+        self.device.destroy_image_view(display_image_view, None);
         self.device
             .free_descriptor_sets(self.descriptor_pool, &descriptor_sets)
             .unwrap();
+        self.device
+            .free_descriptor_sets(self.descriptor_pool, &[resolve_descriptor_set, post_descriptor_set, cloud_shadow_descriptor_set, fog_ambient_descriptor_set])
+            .unwrap();
+
+        if let Some(denoise_descriptor_set) = denoise_descriptor_set {
+            self.device
+                .free_descriptor_sets(self.descriptor_pool, &[denoise_descriptor_set])
+                .unwrap();
+            self.denoise_index = 1 - self.denoise_index;
+            self.denoise_reset = false;
+        }
+        self.previous_render_resolution = render_resolution;
+
+        let gpu_time = self.timestamp_query_pool.and_then(|query_pool| {
+            resolution::read_gpu_time(&self.device, query_pool, self.timestamp_period)
+        });
+
+        // Path tracing is way slower than the raymarcher, that shouldn't drag the resolution down
+        if !self.reference_settings.enabled {
+            self.dynamic_resolution.update(gpu_time);
+        }
+        self.stats.gpu_frame_time = self.dynamic_resolution.gpu_frame_time;
+        self.stats.render_scale = self.dynamic_resolution.scale;
+
+        if self.reference_settings.enabled && accumulate {
+            self.accumulation.samples += 1;
+        }
+        self.stats.reference_samples = self.reference_settings.enabled.then_some(self.accumulation.samples);
+
+        self.taa_index = 1 - self.taa_index;
+        self.taa_reset = false;
+        self.previous_position = self.movement.position.with_w(0f32);
+        self.previous_rays = taa::reprojection_matrix(view_proj);
+        self.stats.exposure = post::read_exposure(&self.exposure_buffer.1);
         
         if let Some(desc_temp) = desc_temp{
             self.device.free_descriptor_sets(self.descriptor_pool, &[desc_temp]).unwrap();
+            self.shadow_cache.resolve = false;
+            self.check_voxel_surface_utilization();
+        }
+    }
+
+    // Grows the surface buffer when it gets close to full or when some faces did not fit
+    pub unsafe fn check_voxel_surface_utilization(&mut self) {
+        let counters = voxel::read_voxel_counters(&self.voxel_surface_readback_buffer.1);
+        let used = counters[0];
+        let overflow = counters[voxel::OVERFLOW_COUNTER];
+
+        let last = self.stats.surface_utilization();
+        self.stats.surface_faces_used = used;
+        self.stats.surface_faces_capacity = self.voxel_surface_capacity;
+        self.stats.surface_voxels = counters[voxel::SURFACE_VOXEL_COUNTER];
+        self.stats.shadow_texels = counters[voxel::TEXEL_BUDGET_COUNTER].min(self.shadow_settings.texel_budget);
+        self.stats.shadow_texel_budget = self.shadow_settings.texel_budget;
+        let utilization = self.stats.surface_utilization();
+
+        if utilization > voxel::SURFACE_UTILIZATION_WARNING && last <= voxel::SURFACE_UTILIZATION_WARNING {
+            log::warn!("voxel surface buffer is at {:.1}% utilization", utilization * 100f32);
+        }
+
+        let required = used + overflow;
+        if overflow == 0 && (required as f32) < self.voxel_surface_capacity as f32 * voxel::SURFACE_UTILIZATION_GROW {
+            return;
+        }
+
+        let new_capacity = (self.voxel_surface_capacity * 2).max(required.next_power_of_two());
+        log::warn!("growing voxel surface buffer from {} to {} faces ({} faces did not fit)", self.voxel_surface_capacity, new_capacity, overflow);
+
+        self.voxel_surface_buffer = voxel::grow_voxel_surface_buffer(
+            &self.device,
+            &mut self.allocator,
+            self.queue,
+            self.pool,
+            &self.debug_marker,
+            self.voxel_surface_resolution,
+            std::mem::take(&mut self.voxel_surface_buffer),
+            self.voxel_surface_capacity,
+            new_capacity,
+        );
+        self.voxel_surface_capacity = new_capacity;
+        self.stats.surface_faces_capacity = new_capacity;
+
+        // Allocate the faces that did not fit last time
+        if overflow > 0 {
+            let region = voxel::DirtyRegion::full();
+            self.dirty = Some(self.dirty.map_or(region, |dirty| dirty.merge(region)));
+            self.shadow_cache.resolve = true;
         }
     }
 
     pub unsafe fn destroy(mut self) {
+        self.time_of_day.save();
+
         self.device.destroy_pipeline(self.render_compute_pipeline, None);
         self.device.destroy_pipeline_layout(self.render_compute_pipeline_layout, None);
         self.device.destroy_descriptor_set_layout(self.render_compute_descriptor_set_layout, None);
@@ -707,6 +1487,46 @@ impl InternalApp {
         self.device.destroy_shader_module(self.voxel_compute_shader_module, None);
         log::info!("destroyed voxel compute pipeline");
 
+        for (descriptor_set_layout, pipeline_layout, pipeline) in self.post_pipelines {
+            self.device.destroy_pipeline(pipeline, None);
+            self.device.destroy_pipeline_layout(pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        }
+        self.device.destroy_shader_module(self.post_shader_module, None);
+        log::info!("destroyed post processing pipelines");
+
+        for (descriptor_set_layout, pipeline_layout, pipeline) in self.denoise_pipelines {
+            self.device.destroy_pipeline(pipeline, None);
+            self.device.destroy_pipeline_layout(pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        }
+        self.device.destroy_shader_module(self.denoise_shader_module, None);
+        log::info!("destroyed denoise pipelines");
+
+        self.device.destroy_pipeline(self.taa_pipeline, None);
+        self.device.destroy_pipeline_layout(self.taa_pipeline_layout, None);
+        self.device.destroy_descriptor_set_layout(self.taa_descriptor_set_layout, None);
+        self.device.destroy_shader_module(self.taa_shader_module, None);
+        log::info!("destroyed taa pipeline");
+
+        self.device.destroy_pipeline(self.reference_pipeline, None);
+        self.device.destroy_pipeline_layout(self.reference_pipeline_layout, None);
+        self.device.destroy_descriptor_set_layout(self.reference_descriptor_set_layout, None);
+        self.device.destroy_shader_module(self.reference_shader_module, None);
+        log::info!("destroyed reference pipeline");
+
+        self.device.destroy_pipeline(self.cloud_shadow_pipeline, None);
+        self.device.destroy_pipeline_layout(self.cloud_shadow_pipeline_layout, None);
+        self.device.destroy_descriptor_set_layout(self.cloud_shadow_descriptor_set_layout, None);
+        self.device.destroy_shader_module(self.cloud_shadow_shader_module, None);
+        log::info!("destroyed cloud shadow pipeline");
+
+        self.device.destroy_pipeline(self.fog_ambient_pipeline, None);
+        self.device.destroy_pipeline_layout(self.fog_ambient_pipeline_layout, None);
+        self.device.destroy_descriptor_set_layout(self.fog_ambient_descriptor_set_layout, None);
+        self.device.destroy_shader_module(self.fog_ambient_shader_module, None);
+        log::info!("destroyed fog ambient pipeline");
+
         self.device
             .destroy_descriptor_pool(self.descriptor_pool, None);
         log::info!("destroyed descriptor pool");
@@ -721,6 +1541,11 @@ impl InternalApp {
         self.allocator.free(self.voxel_surface_index_image.1).unwrap();
         log::info!("destroyed voxel surface index image");
 
+        self.device.destroy_image_view(self.voxel_light_image.2, None);
+        self.device.destroy_image(self.voxel_light_image.0, None);
+        self.allocator.free(self.voxel_light_image.1).unwrap();
+        log::info!("destroyed voxel light image");
+
         self.device.destroy_buffer(self.voxel_surface_buffer.0, None);
         self.allocator.free(self.voxel_surface_buffer.1).unwrap();
         log::info!("destroyed voxel buffer");
@@ -729,10 +1554,94 @@ impl InternalApp {
         self.allocator.free(self.voxel_surface_counter_buffer.1).unwrap();
         log::info!("destroyed voxel counter buffer");
 
+        self.device.destroy_buffer(self.voxel_surface_readback_buffer.0, None);
+        self.allocator.free(self.voxel_surface_readback_buffer.1).unwrap();
+        log::info!("destroyed voxel counter readback buffer");
+
+        self.device.destroy_buffer(self.voxel_surface_free_list_buffer.0, None);
+        self.allocator.free(self.voxel_surface_free_list_buffer.1).unwrap();
+        log::info!("destroyed voxel free list buffer");
+
+        self.device.destroy_buffer(self.voxel_surface_list_buffer.0, None);
+        self.allocator.free(self.voxel_surface_list_buffer.1).unwrap();
+        log::info!("destroyed voxel surface list buffer");
+
+        self.device.destroy_buffer(self.voxel_dispatch_buffer.0, None);
+        self.allocator.free(self.voxel_dispatch_buffer.1).unwrap();
+        log::info!("destroyed voxel dispatch buffer");
+
+        self.device.destroy_buffer(self.voxel_update_settings_buffer.0, None);
+        self.allocator.free(self.voxel_update_settings_buffer.1).unwrap();
+        log::info!("destroyed voxel update settings buffer");
+
+        self.device.destroy_buffer(self.point_light_buffer.0, None);
+        self.allocator.free(self.point_light_buffer.1).unwrap();
+        log::info!("destroyed point light buffer");
+
+        self.device.destroy_buffer(self.material_buffer.0, None);
+        self.allocator.free(self.material_buffer.1).unwrap();
+        log::info!("destroyed material buffer");
+
+        self.device.destroy_sampler(self.block_texture_sampler, None);
+        self.device.destroy_image_view(self.block_textures.2, None);
+        self.device.destroy_image(self.block_textures.0, None);
+        self.allocator.free(self.block_textures.1).unwrap();
+        log::info!("destroyed block textures");
+
+        self.device.destroy_sampler(self.environment_sampler, None);
+        self.device.destroy_image_view(self.environment_image.2, None);
+        self.device.destroy_image(self.environment_image.0, None);
+        self.allocator.free(self.environment_image.1).unwrap();
+        self.device.destroy_buffer(self.environment_buffer.0, None);
+        self.allocator.free(self.environment_buffer.1).unwrap();
+        log::info!("destroyed environment map");
+
+        image::destroy_storage_image(&self.device, &mut self.allocator, self.cloud_shadow_image);
+        self.device.destroy_buffer(self.cloud_buffer.0, None);
+        self.allocator.free(self.cloud_buffer.1).unwrap();
+        log::info!("destroyed cloud shadow map and buffer");
+
+        self.device.destroy_buffer(self.view_buffer.0, None);
+        self.allocator.free(self.view_buffer.1).unwrap();
+        log::info!("destroyed view buffer");
+
+        self.device.destroy_buffer(self.fog_ambient_buffer.0, None);
+        self.allocator.free(self.fog_ambient_buffer.1).unwrap();
+        log::info!("destroyed fog ambient buffer");
+
+        self.device.destroy_buffer(self.histogram_buffer.0, None);
+        self.allocator.free(self.histogram_buffer.1).unwrap();
+        self.device.destroy_buffer(self.exposure_buffer.0, None);
+        self.allocator.free(self.exposure_buffer.1).unwrap();
+        self.device.destroy_buffer(self.lut_buffer.0, None);
+        self.allocator.free(self.lut_buffer.1).unwrap();
+        log::info!("destroyed histogram, exposure and color grading buffers");
+
+        post::destroy_bloom_image(&self.device, &mut self.allocator, self.bloom_image);
+        log::info!("destroyed bloom image");
+
+        for history in self.taa_history {
+            image::destroy_storage_image(&self.device, &mut self.allocator, history);
+        }
+        log::info!("destroyed taa history images");
+
+        gbuffer::destroy_gbuffer(&self.device, &mut self.allocator, self.gbuffer);
+        log::info!("destroyed gbuffer images");
+
+        image::destroy_storage_image(&self.device, &mut self.allocator, self.accumulation_image);
+        log::info!("destroyed reference accumulation image");
+
+        denoise::destroy_denoise_images(&self.device, &mut self.allocator, self.denoise_images);
+        log::info!("destroyed denoise images");
+
         // TODO: Just cope with the error messages vro
         self.device
             .wait_for_fences(&[self.end_fence], true, u64::MAX)
             .unwrap();
+        if let Some(query_pool) = self.timestamp_query_pool {
+            self.device.destroy_query_pool(query_pool, None);
+        }
+
         self.device.destroy_semaphore(self.begin_semaphore, None);
         self.device.destroy_semaphore(self.end_semaphore, None);
         self.device.destroy_fence(self.end_fence, None);
@@ -743,7 +1652,7 @@ impl InternalApp {
         self.surface_loader.destroy_surface(self.surface_khr, None);
         log::info!("destroyed surface");
 
-        for (image, allocation) in self.rt_images {
+        for (image, allocation) in self.rt_images.into_iter().chain(self.display_images) {
             self.device.destroy_image(image, None);
             self.allocator.free(allocation).unwrap();
         }
@@ -805,11 +1714,188 @@ impl ApplicationHandler for App {
                     }
                 }
 
+                // Force a full resolve of the shadow cache
+                if inner.input.get_button(KeyCode::F6).pressed() {
+                    inner.shadow_cache.resolve = true;
+                }
+
+                // Toggle GI
+                if inner.input.get_button(KeyCode::F7).pressed() {
+                    inner.gi_settings.enabled = !inner.gi_settings.enabled;
+                    log::info!("gi enabled: {}", inner.gi_settings.enabled);
+                    if !inner.gi_settings.enabled {
+                        log::info!("emissive voxels will only light their surroundings through the block light");
+                    }
+                }
+
+                // Cycle through the GI quality presets
+                if inner.input.get_button(KeyCode::F8).pressed() {
+                    inner.gi_settings.quality = inner.gi_settings.quality.next();
+                    log::info!("gi quality: {:?}", inner.gi_settings.quality);
+                }
+
+                // Toggle the sky visibility, cycle through its quality presets or change its ray count
+                if inner.input.get_button(KeyCode::F9).pressed() {
+                    inner.sky_visibility_settings.enabled = !inner.sky_visibility_settings.enabled;
+                    log::info!("sky visibility enabled: {}", inner.sky_visibility_settings.enabled);
+                }
+
+                if inner.input.get_button(KeyCode::F10).pressed() {
+                    inner.sky_visibility_settings.quality = inner.sky_visibility_settings.quality.next();
+                    log::info!("sky visibility quality: {:?}", inner.sky_visibility_settings.quality);
+                }
+
+                if inner.input.get_button(KeyCode::F11).pressed() {
+                    inner.sky_visibility_settings.next_rays();
+                    log::info!("sky visibility rays: {}", inner.sky_visibility_settings.rays);
+                }
+
+                // Pause the day/night cycle or skip forward/backward by an hour
+                if inner.input.get_button(KeyCode::KeyP).pressed() {
+                    inner.time_of_day.paused = !inner.time_of_day.paused;
+                    log::info!("time of day paused: {}", inner.time_of_day.paused);
+                }
+
+                if inner.input.get_button(KeyCode::BracketRight).pressed() {
+                    inner.time_of_day.skip_hours(1f32);
+                    log::info!("time of day: {:.1}h", inner.time_of_day.hours());
+                }
+
+                if inner.input.get_button(KeyCode::BracketLeft).pressed() {
+                    inner.time_of_day.skip_hours(-1f32);
+                    log::info!("time of day: {:.1}h", inner.time_of_day.hours());
+                }
+
+                // Toggle the volumetric fog
+                if inner.input.get_button(KeyCode::KeyG).pressed() {
+                    inner.fog_settings.enabled = !inner.fog_settings.enabled;
+                    log::info!("fog enabled: {}", inner.fog_settings.enabled);
+                }
+
+                // Cycle through the tonemappers, or toggle the auto exposure
+                if inner.input.get_button(KeyCode::KeyT).pressed() {
+                    inner.post_settings.tonemapper = inner.post_settings.tonemapper.next();
+                    log::info!("tonemapper: {:?}", inner.post_settings.tonemapper);
+                }
+
+                if inner.input.get_button(KeyCode::KeyY).pressed() {
+                    inner.post_settings.auto_exposure = !inner.post_settings.auto_exposure;
+                    log::info!("auto exposure enabled: {}", inner.post_settings.auto_exposure);
+                }
+
+                // Toggle the temporal anti-aliasing (the image still gets upscaled without it)
+                if inner.input.get_button(KeyCode::KeyU).pressed() {
+                    inner.taa_settings.enabled = !inner.taa_settings.enabled;
+                    log::info!("taa enabled: {}", inner.taa_settings.enabled);
+                }
+
+                // Toggle the denoiser of the raymarcher lighting
+                if inner.input.get_button(KeyCode::KeyN).pressed() {
+                    inner.denoise_settings.enabled = !inner.denoise_settings.enabled;
+                    inner.denoise_reset = true;
+                    log::info!("denoiser enabled: {}", inner.denoise_settings.enabled);
+                }
+
+                // Switch between the real-time renderer and the progressive path traced reference
+                if inner.input.get_button(KeyCode::KeyH).pressed() {
+                    inner.reference_settings.enabled = !inner.reference_settings.enabled;
+                    inner.accumulation.reset();
+                    inner.taa_reset = true;
+                    inner.denoise_reset = true;
+                    log::info!("path traced reference enabled: {}", inner.reference_settings.enabled);
+                }
+
+                // Toggle the dynamic resolution (falls back to a fixed scale)
+                if inner.input.get_button(KeyCode::KeyR).pressed() {
+                    inner.dynamic_resolution.enabled = !inner.dynamic_resolution.enabled;
+                    log::info!("dynamic resolution enabled: {}", inner.dynamic_resolution.enabled);
+                }
+
+                // Toggle the post effects (bloom, color grading, vignette, film grain)
+                if inner.input.get_button(KeyCode::Digit1).pressed() {
+                    inner.post_settings.bloom.enabled = !inner.post_settings.bloom.enabled;
+                    log::info!("bloom enabled: {}", inner.post_settings.bloom.enabled);
+                }
+
+                if inner.input.get_button(KeyCode::Digit2).pressed() {
+                    inner.post_settings.color_grading.enabled = !inner.post_settings.color_grading.enabled;
+                    log::info!("color grading enabled: {}", inner.post_settings.color_grading.enabled);
+                }
+
+                if inner.input.get_button(KeyCode::Digit3).pressed() {
+                    inner.post_settings.vignette.enabled = !inner.post_settings.vignette.enabled;
+                    log::info!("vignette enabled: {}", inner.post_settings.vignette.enabled);
+                }
+
+                if inner.input.get_button(KeyCode::Digit4).pressed() {
+                    inner.post_settings.film_grain.enabled = !inner.post_settings.film_grain.enabled;
+                    log::info!("film grain enabled: {}", inner.post_settings.film_grain.enabled);
+                }
+
+                // Switch between the environment map and the procedural sky, or rotate the map
+                if inner.input.get_button(KeyCode::KeyE).pressed() {
+                    inner.environment_settings.enabled = !inner.environment_settings.enabled;
+                    inner.accumulation.reset();
+                    log::info!("environment map enabled: {}", inner.environment_settings.enabled && inner.environment_ambient.is_some());
+                }
+
+                let rotation = inner.input.get_button(KeyCode::Period).held() as i32 - inner.input.get_button(KeyCode::Comma).held() as i32;
+                if rotation != 0 {
+                    inner.environment_settings.rotation += rotation as f32 * inner.environment_settings.rotation_speed * delta;
+                    inner.accumulation.reset();
+                }
+
+                // Toggle the clouds, change how much of the sky they cover, or steer the wind
+                if inner.input.get_button(KeyCode::KeyC).pressed() {
+                    inner.cloud_settings.enabled = !inner.cloud_settings.enabled;
+                    inner.accumulation.reset();
+                    log::info!("clouds enabled: {}", inner.cloud_settings.enabled);
+                }
+
+                let coverage = inner.input.get_button(KeyCode::KeyB).held() as i32 - inner.input.get_button(KeyCode::KeyV).held() as i32;
+                if coverage != 0 {
+                    let settings = &mut inner.cloud_settings;
+                    settings.coverage = (settings.coverage + coverage as f32 * settings.coverage_speed * delta).clamp(0.0, 1.0);
+                    inner.accumulation.reset();
+                }
+
+                let turn = inner.input.get_button(KeyCode::ArrowRight).held() as i32 - inner.input.get_button(KeyCode::ArrowLeft).held() as i32;
+                let accelerate = inner.input.get_button(KeyCode::ArrowUp).held() as i32 - inner.input.get_button(KeyCode::ArrowDown).held() as i32;
+                if turn != 0 || accelerate != 0 {
+                    let settings = &mut inner.cloud_settings;
+                    let angle = turn as f32 * settings.wind_turn_speed * delta;
+                    let acceleration = accelerate as f32 * settings.wind_acceleration * delta;
+                    settings.steer(angle, acceleration);
+                }
+
+                // Drop a point light at the camera position, or remove all of them
+                if inner.input.get_button(KeyCode::KeyL).pressed() {
+                    let hue = inner.point_lights.len() as f32 * 2.4f32;
+                    let color = vek::Vec3::new(hue.sin(), (hue + 2.094f32).sin(), (hue + 4.188f32).sin()) * 0.5f32 + 0.5f32;
+                    let light = lights::PointLight::new(inner.movement.position, color, 16f32, 2f32);
+                    inner.point_lights.push(light);
+                }
+
+                if inner.input.get_button(KeyCode::KeyK).pressed() {
+                    inner.point_lights.clear();
+                }
+
                 let left = inner.input.get_button(Button::Mouse(MouseButton::Left)).held();
                 let right = inner.input.get_button(Button::Mouse(MouseButton::Right)).held();
+                let middle = inner.input.get_button(Button::Mouse(MouseButton::Middle)).held();
+
+                if left || right || middle {
+                    inner.click(voxel::Voxel {
+                        active: true,
+                        reflective: false,
+                        refractive: left,
+                        placed: true,
+                        emissive: middle && !left,
+                    });
+                }
 
-                if left || right {
-                    inner.click(left);
+                if inner.stats.update(delta) {
+                    inner.window.set_title(&inner.stats.to_string());
                 }
 
                 inner.window.request_redraw();
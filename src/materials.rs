@@ -0,0 +1,97 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+// Indices in the material buffer, picked from the voxel flags. Must match the values in material.slang
+pub const MATERIAL_MIRROR: usize = 0;
+pub const MATERIAL_GLASS: usize = 1;
+pub const MATERIAL_COUNT: usize = 2;
+
+// Must match Material in material.slang
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct Material {
+    // Reflectance at normal incidence of mirrors, glass uses the one given by its IOR instead
+    pub tint: vek::Vec3<f32>,
+    pub ior: f32,
+
+    // How much of each channel gets absorbed per voxel travelled inside glass (Beer-Lambert)
+    pub absorption: vek::Vec3<f32>,
+
+    // 0 is perfectly smooth, higher values spread the reflections and refractions
+    pub roughness: f32,
+}
+
+// Materials of the specular voxels, the diffuse ones are still shaded by light() in lighting.slang
+pub struct MaterialSettings {
+    pub materials: [Material; MATERIAL_COUNT],
+
+    // Max number of reflections/refractions a primary ray goes through before we give up on it
+    pub bounces: u32,
+}
+
+impl Default for MaterialSettings {
+    fn default() -> Self {
+        let mirror = Material {
+            tint: vek::Vec3::new(0.95, 0.93, 0.88),
+            ior: 1.0,
+            absorption: vek::Vec3::zero(),
+            roughness: 0.02,
+        };
+
+        let glass = Material {
+            tint: vek::Vec3::one(),
+            ior: 1.5,
+            absorption: vek::Vec3::new(0.3, 0.08, 0.12),
+            roughness: 0.0,
+        };
+
+        Self {
+            materials: [mirror, glass],
+            bounces: 8,
+        }
+    }
+}
+
+// Host visible so the settings can be tweaked at runtime without any staging
+pub unsafe fn create_material_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size((size_of::<Material>() * MATERIAL_COUNT) as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Material Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"material buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Writes the materials to the mapped buffer, cheap enough to do every frame
+pub fn upload_materials(allocation: &mut Allocation, settings: &MaterialSettings) {
+    let raw = bytemuck::cast_slice::<Material, u8>(&settings.materials);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+}
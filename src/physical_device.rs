@@ -42,17 +42,31 @@ pub(super) unsafe fn get_physical_device_score(
         .is_some();
 
     log::info!("present modes supported: {present_modes_supported}");
+    // UNORM since the post pass does the sRGB encoding itself
     let surface_compatible = surface_formats
         .iter()
         .find(|format| {
-            let format_ = format.format == vk::Format::B8G8R8A8_SRGB;
+            let format_ = matches!(format.format, vk::Format::B8G8R8A8_UNORM | vk::Format::R8G8B8A8_UNORM);
             let color_space_ = format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR;
             format_ && color_space_
         })
         .is_some();
     log::info!("compatible surface: {surface_compatible}");
 
-    if !double_buffering_supported || !present_modes_supported || !surface_compatible {
+    let features = instance.get_physical_device_features(physical_device);
+    let surface_index_format_properties = instance
+        .get_physical_device_format_properties(physical_device, crate::voxel::SURFACE_INDEX_FORMAT);
+    let surface_index_format_supported = features.shader_storage_image_extended_formats == vk::TRUE
+        && surface_index_format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::STORAGE_IMAGE);
+    log::info!("surface index format supported: {surface_index_format_supported}");
+
+    // The bloom kernels pick their mip out of an array of storage images with a push constant
+    let bloom_indexing_supported = features.shader_storage_image_array_dynamic_indexing == vk::TRUE;
+    log::info!("bloom mip indexing supported: {bloom_indexing_supported}");
+
+    if !double_buffering_supported || !present_modes_supported || !surface_compatible || !surface_index_format_supported || !bloom_indexing_supported {
         return None;
     }
 
@@ -5,25 +5,152 @@ use bytemuck::{Pod, Zeroable};
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct PushConstants {
     pub screen_resolution: vek::Vec2<f32>,
-    pub _padding: vek::Vec2<f32>,
-    pub matrix: vek::Mat4<f32>,
+    pub gi_strength: f32,
+    pub sky_visibility_strength: f32,
     pub position: vek::Vec4<f32>,
     pub sun: vek::Vec4<f32>,
+    pub fog_color: vek::Vec4<f32>,
+    pub fog_height_falloff: f32,
+    pub fog_anisotropy: f32,
+    pub fog_scattering: f32,
+    pub fog_steps: u32,
+    pub frame: u32,
+
+    // Bitmask of the optional G-buffer images to write (see gbuffer.rs)
+    // Also keeps the fields below aligned the same way they are in the shader
+    pub gbuffer_outputs: u32,
+    pub jitter: vek::Vec2<f32>,
+
+    // Reflection/refraction limit of the primary rays (see materials.rs)
+    pub max_bounces: u32,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct PushConstants2 {
-    pub forward: vek::Vec4<f32>,
+    pub view_proj: vek::Mat4<f32>,
     pub position: vek::Vec4<f32>,
     pub sun: vek::Vec4<f32>,
     pub tick: u32,
     pub delta: f32,
+    pub sun_epoch: u32,
+    pub resolve: u32,
+    pub light_count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants3 {
+    pub region_min: vek::Vec4<u32>,
+    pub region_max: vek::Vec4<u32>,
+    pub capacity: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants4 {
+    pub resolution: vek::Vec2<u32>,
+    pub min_log_luminance: f32,
+    pub log_luminance_range: f32,
+    pub delta: f32,
+    pub adaptation_speed: f32,
+    pub exposure_compensation: f32,
+    pub auto_exposure: u32,
+    pub manual_exposure: f32,
+    pub tonemapper: u32,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub bloom_mip: u32,
+    pub lut_size: u32,
+    pub lut_strength: f32,
+    pub vignette_intensity: f32,
+    pub vignette_smoothness: f32,
+    pub grain_intensity: f32,
+    pub frame: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants5 {
+    pub input_resolution: vek::Vec2<u32>,
+    pub output_resolution: vek::Vec2<u32>,
+    pub jitter: vek::Vec2<f32>,
+    pub blend: f32,
+    pub reset: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants6 {
+    pub resolution: vek::Vec2<u32>,
+    pub samples: u32,
+    pub bounces: u32,
+    pub matrix: vek::Mat4<f32>,
+    pub position: vek::Vec4<f32>,
+    pub sun: vek::Vec4<f32>,
+    pub light_count: u32,
+    pub accumulate: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants7 {
+    pub resolution: vek::Vec2<u32>,
+    pub previous_resolution: vek::Vec2<u32>,
+    pub index: u32,
+    pub source: u32,
+    pub step: u32,
+    pub reset: u32,
+    pub alpha: f32,
+    pub moments_alpha: f32,
+    pub phi_color: f32,
+    pub phi_normal: f32,
+    pub phi_depth: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct PushConstants8 {
+    pub sun: vek::Vec4<f32>,
 }
 
+// Indices of the kernels returned by create_compute_voxel_pipelines
+pub const VOXEL_GENERATE: usize = 0;
+pub const VOXEL_UPDATE: usize = 1;
+pub const VOXEL_RELEASE: usize = 2;
+pub const VOXEL_ALLOCATE: usize = 3;
+pub const VOXEL_INVALIDATE: usize = 4;
+pub const VOXEL_COMPACT: usize = 5;
+pub const VOXEL_PREPARE: usize = 6;
+pub const VOXEL_PROPAGATE: usize = 7;
+
+// Indices of the kernels returned by create_post_pipelines
+pub const POST_HISTOGRAM: usize = 0;
+pub const POST_EXPOSURE: usize = 1;
+pub const POST_TONEMAP: usize = 2;
+pub const POST_BLOOM_DOWNSAMPLE: usize = 3;
+pub const POST_BLOOM_UPSAMPLE: usize = 4;
+pub const POST_GRADE: usize = 5;
+pub const POST_VIGNETTE: usize = 6;
+pub const POST_GRAIN: usize = 7;
+pub const POST_ENCODE: usize = 8;
+
+// Entry points of post.slang, in the same order as the indices above
+const POST_KERNELS: [&std::ffi::CStr; 9] = [c"histogram", c"exposure", c"main", c"downsample", c"upsample", c"grade", c"vignette", c"grain", c"encode"];
+
+// Indices of the kernels returned by create_denoise_pipelines
+pub const DENOISE_TEMPORAL: usize = 0;
+pub const DENOISE_ESTIMATE: usize = 1;
+pub const DENOISE_ATROUS: usize = 2;
+pub const DENOISE_MODULATE: usize = 3;
+
+// Entry points of denoise.slang, in the same order as the indices above
+const DENOISE_KERNELS: [&std::ffi::CStr; 4] = [c"main", c"estimate", c"atrous", c"modulate"];
+
 pub unsafe fn create_render_compute_pipeline(
     raw: &[u32],
     device: &ash::Device,
+    surface_resolution: u32,
 ) -> (
     vk::ShaderModule,
     vk::DescriptorSetLayout,
@@ -37,10 +164,21 @@ pub unsafe fn create_render_compute_pipeline(
         .create_shader_module(&render_compute_shader_module_create_info, None)
         .unwrap();
 
+    // SURFACE_RESOLUTION in surface.slang
+    let render_specialization_map_entries = [vk::SpecializationMapEntry::default()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let render_specialization_data = surface_resolution.to_ne_bytes();
+    let render_specialization_info = vk::SpecializationInfo::default()
+        .map_entries(&render_specialization_map_entries)
+        .data(&render_specialization_data);
+
     let render_compute_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
         .flags(vk::PipelineShaderStageCreateFlags::empty())
         .name(c"main")
         .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&render_specialization_info)
         .module(render_compute_shader_module);
 
     let render_descriptor_set_layout_binding_rt_image = vk::DescriptorSetLayoutBinding::default()
@@ -63,11 +201,86 @@ pub unsafe fn create_render_compute_pipeline(
         .stage_flags(vk::ShaderStageFlags::COMPUTE)
         .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
         .descriptor_count(1);
+    let render_descriptor_set_layout_binding_voxel_light_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(4)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_motion_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(5)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+
+    // Depth, normal, material, voxel coordinate, illumination and albedo images of the G-buffer
+    let render_descriptor_set_layout_binding_gbuffer_images = [6, 7, 8, 9, 10, 11].map(|binding| {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+    });
+    let render_descriptor_set_layout_binding_material_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(12)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_block_textures = vk::DescriptorSetLayoutBinding::default()
+        .binding(13)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_environment_map = vk::DescriptorSetLayoutBinding::default()
+        .binding(14)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_environment_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(15)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_cloud_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(16)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_cloud_shadow_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(17)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_view_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(18)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let render_descriptor_set_layout_binding_fog_ambient_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(19)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
     let render_descriptor_set_layout_bindings = [
         render_descriptor_set_layout_binding_rt_image,
         render_descriptor_set_layout_binding_voxel_image,
         render_descriptor_set_layout_binding_voxel_surface_buffer,
-        render_descriptor_set_layout_binding_voxel_surface_index_image
+        render_descriptor_set_layout_binding_voxel_surface_index_image,
+        render_descriptor_set_layout_binding_voxel_light_image,
+        render_descriptor_set_layout_binding_motion_image,
+        render_descriptor_set_layout_binding_gbuffer_images[0],
+        render_descriptor_set_layout_binding_gbuffer_images[1],
+        render_descriptor_set_layout_binding_gbuffer_images[2],
+        render_descriptor_set_layout_binding_gbuffer_images[3],
+        render_descriptor_set_layout_binding_gbuffer_images[4],
+        render_descriptor_set_layout_binding_gbuffer_images[5],
+        render_descriptor_set_layout_binding_material_buffer,
+        render_descriptor_set_layout_binding_block_textures,
+        render_descriptor_set_layout_binding_environment_map,
+        render_descriptor_set_layout_binding_environment_buffer,
+        render_descriptor_set_layout_binding_cloud_buffer,
+        render_descriptor_set_layout_binding_cloud_shadow_image,
+        render_descriptor_set_layout_binding_view_buffer,
+        render_descriptor_set_layout_binding_fog_ambient_buffer,
     ];
 
     let render_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
@@ -114,14 +327,323 @@ pub unsafe fn create_render_compute_pipeline(
     )
 }
 
+pub unsafe fn create_taa_pipeline(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (
+    vk::ShaderModule,
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+) {
+    let taa_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let taa_shader_module = device
+        .create_shader_module(&taa_shader_module_create_info, None)
+        .unwrap();
+
+    let taa_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"main")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(taa_shader_module);
+
+    // Current color, motion, history and resolved images
+    let taa_descriptor_set_layout_bindings = [0, 1, 2, 3].map(|binding| {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+    });
+
+    let taa_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&taa_descriptor_set_layout_bindings);
+
+    let taa_descriptor_set_layout = device
+        .create_descriptor_set_layout(&taa_descriptor_set_layout_create_info, None)
+        .unwrap();
+    let taa_descriptor_set_layouts = [taa_descriptor_set_layout];
+
+    let taa_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants5>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let taa_push_constants = [taa_push_constant_range];
+
+    let taa_pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&taa_push_constants)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&taa_descriptor_set_layouts);
+
+    let taa_pipeline_layout = device
+        .create_pipeline_layout(&taa_pipeline_layout_create_info, None)
+        .unwrap();
+
+    let taa_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(taa_pipeline_layout)
+        .stage(taa_stage_create_info);
+    let taa_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[taa_pipeline_create_info],
+            None,
+        )
+        .unwrap();
+
+    (
+        taa_shader_module,
+        taa_descriptor_set_layout,
+        taa_pipeline_layout,
+        taa_pipelines[0],
+    )
+}
+
+pub unsafe fn create_cloud_shadow_pipeline(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (
+    vk::ShaderModule,
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+) {
+    let cloud_shadow_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let cloud_shadow_shader_module = device
+        .create_shader_module(&cloud_shadow_shader_module_create_info, None)
+        .unwrap();
+
+    let cloud_shadow_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"main")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(cloud_shadow_shader_module);
+
+    // Shadow map and the cloud settings
+    let cloud_shadow_descriptor_set_layout_bindings = [0, 1].map(|binding| {
+        let descriptor_type = match binding {
+            1 => vk::DescriptorType::STORAGE_BUFFER,
+            _ => vk::DescriptorType::STORAGE_IMAGE,
+        };
+
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+    });
+
+    let cloud_shadow_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&cloud_shadow_descriptor_set_layout_bindings);
+
+    let cloud_shadow_descriptor_set_layout = device
+        .create_descriptor_set_layout(&cloud_shadow_descriptor_set_layout_create_info, None)
+        .unwrap();
+    let cloud_shadow_descriptor_set_layouts = [cloud_shadow_descriptor_set_layout];
+
+    let cloud_shadow_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants8>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let cloud_shadow_push_constants = [cloud_shadow_push_constant_range];
+
+    let cloud_shadow_pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&cloud_shadow_push_constants)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&cloud_shadow_descriptor_set_layouts);
+
+    let cloud_shadow_pipeline_layout = device
+        .create_pipeline_layout(&cloud_shadow_pipeline_layout_create_info, None)
+        .unwrap();
+
+    let cloud_shadow_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(cloud_shadow_pipeline_layout)
+        .stage(cloud_shadow_stage_create_info);
+    let cloud_shadow_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[cloud_shadow_pipeline_create_info],
+            None,
+        )
+        .unwrap();
+
+    (
+        cloud_shadow_shader_module,
+        cloud_shadow_descriptor_set_layout,
+        cloud_shadow_pipeline_layout,
+        cloud_shadow_pipelines[0],
+    )
+}
+
+pub unsafe fn create_fog_ambient_pipeline(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (
+    vk::ShaderModule,
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+) {
+    let fog_ambient_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let fog_ambient_shader_module = device
+        .create_shader_module(&fog_ambient_shader_module_create_info, None)
+        .unwrap();
+
+    let fog_ambient_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"main")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(fog_ambient_shader_module);
+
+    // Fog ambient output and the environment settings
+    let fog_ambient_descriptor_set_layout_bindings = [0, 1].map(|binding| {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+    });
+
+    let fog_ambient_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&fog_ambient_descriptor_set_layout_bindings);
+
+    let fog_ambient_descriptor_set_layout = device
+        .create_descriptor_set_layout(&fog_ambient_descriptor_set_layout_create_info, None)
+        .unwrap();
+    let fog_ambient_descriptor_set_layouts = [fog_ambient_descriptor_set_layout];
+
+    let fog_ambient_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants8>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let fog_ambient_push_constants = [fog_ambient_push_constant_range];
+
+    let fog_ambient_pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&fog_ambient_push_constants)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&fog_ambient_descriptor_set_layouts);
+
+    let fog_ambient_pipeline_layout = device
+        .create_pipeline_layout(&fog_ambient_pipeline_layout_create_info, None)
+        .unwrap();
+
+    let fog_ambient_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(fog_ambient_pipeline_layout)
+        .stage(fog_ambient_stage_create_info);
+    let fog_ambient_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[fog_ambient_pipeline_create_info],
+            None,
+        )
+        .unwrap();
+
+    (
+        fog_ambient_shader_module,
+        fog_ambient_descriptor_set_layout,
+        fog_ambient_pipeline_layout,
+        fog_ambient_pipelines[0],
+    )
+}
+
+pub unsafe fn create_reference_pipeline(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (
+    vk::ShaderModule,
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+) {
+    let reference_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let reference_shader_module = device
+        .create_shader_module(&reference_shader_module_create_info, None)
+        .unwrap();
+
+    let reference_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"main")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(reference_shader_module);
+
+    // Output, voxels and accumulation images, the point lights and the materials, the block textures and the environment
+    // then the clouds and their shadow map
+    let reference_descriptor_set_layout_bindings = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9].map(|binding| {
+        let descriptor_type = match binding {
+            3 | 4 | 7 | 8 => vk::DescriptorType::STORAGE_BUFFER,
+            5 | 6 => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            _ => vk::DescriptorType::STORAGE_IMAGE,
+        };
+
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+    });
+
+    let reference_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&reference_descriptor_set_layout_bindings);
+
+    let reference_descriptor_set_layout = device
+        .create_descriptor_set_layout(&reference_descriptor_set_layout_create_info, None)
+        .unwrap();
+    let reference_descriptor_set_layouts = [reference_descriptor_set_layout];
+
+    let reference_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants6>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let reference_push_constants = [reference_push_constant_range];
+
+    let reference_pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&reference_push_constants)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&reference_descriptor_set_layouts);
+
+    let reference_pipeline_layout = device
+        .create_pipeline_layout(&reference_pipeline_layout_create_info, None)
+        .unwrap();
+
+    let reference_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(reference_pipeline_layout)
+        .stage(reference_stage_create_info);
+    let reference_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[reference_pipeline_create_info],
+            None,
+        )
+        .unwrap();
+
+    (
+        reference_shader_module,
+        reference_descriptor_set_layout,
+        reference_pipeline_layout,
+        reference_pipelines[0],
+    )
+}
+
 pub unsafe fn create_compute_voxel_pipelines(
     raw: &[u32],
     device: &ash::Device,
+    surface_resolution: u32,
 ) -> (vk::ShaderModule, [(
     vk::DescriptorSetLayout,
     vk::PipelineLayout,
     vk::Pipeline,
-); 2]) {
+); 8]) {
     let compute_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
         .code(raw)
         .flags(vk::ShaderModuleCreateFlags::empty());
@@ -129,16 +651,70 @@ pub unsafe fn create_compute_voxel_pipelines(
         .create_shader_module(&compute_shader_module_create_info, None)
         .unwrap();
 
+    // SURFACE_RESOLUTION in surface.slang
+    let specialization_map_entries = [vk::SpecializationMapEntry::default()
+        .constant_id(0)
+        .offset(0)
+        .size(size_of::<u32>())];
+    let specialization_data = surface_resolution.to_ne_bytes();
+    let specialization_info = vk::SpecializationInfo::default()
+        .map_entries(&specialization_map_entries)
+        .data(&specialization_data);
+
     let compute_init_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
         .flags(vk::PipelineShaderStageCreateFlags::empty())
         .name(c"main")
         .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
         .module(compute_shader_module);
 
     let compute_test_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
         .flags(vk::PipelineShaderStageCreateFlags::empty())
         .name(c"update")
         .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_release_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"release")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_allocate_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"allocate")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_invalidate_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"invalidate")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_compact_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"compact")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_prepare_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"prepare")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
+        .module(compute_shader_module);
+
+    let compute_propagate_stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+        .flags(vk::PipelineShaderStageCreateFlags::empty())
+        .name(c"propagate")
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .specialization_info(&specialization_info)
         .module(compute_shader_module);
 
     let descriptor_set_layout_binding_voxel_image = vk::DescriptorSetLayoutBinding::default()
@@ -187,12 +763,48 @@ pub unsafe fn create_compute_voxel_pipelines(
         .stage_flags(vk::ShaderStageFlags::COMPUTE)
         .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
         .descriptor_count(1);
+    let descriptor_set_layout_binding_free_list_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(4)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let descriptor_set_layout_binding_surface_voxels_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(5)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let descriptor_set_layout_binding_dispatch_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(6)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let descriptor_set_layout_binding_point_light_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(7)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let descriptor_set_layout_binding_light_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(8)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+    let descriptor_set_layout_binding_update_settings_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(9)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
 
     let descriptor_set_layout_bindings = [
         descriptor_set_layout_binding_voxel_image,
         descriptor_set_layout_binding_surface_buffer,
         descriptor_set_layout_binding_voxel_surface_index_image,
-        descriptor_set_layout_binding_counter_buffer
+        descriptor_set_layout_binding_counter_buffer,
+        descriptor_set_layout_binding_free_list_buffer,
+        descriptor_set_layout_binding_surface_voxels_buffer,
+        descriptor_set_layout_binding_dispatch_buffer,
+        descriptor_set_layout_binding_point_light_buffer,
+        descriptor_set_layout_binding_light_image,
+        descriptor_set_layout_binding_update_settings_buffer,
     ];
     
     let descriptor_set_test_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
@@ -223,17 +835,319 @@ pub unsafe fn create_compute_voxel_pipelines(
         .layout(compute_pipeline_test_layout)
         .stage(compute_test_stage_create_info);
 
+    // The rebuild kernels (release, allocate, invalidate, compact, prepare, propagate) use the same bindings as the update kernel
+    // Each one gets its own copy of the layouts so we can destroy them uniformly
+    let compute_descriptor_release_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_allocate_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_invalidate_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_compact_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_prepare_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_release_set_layouts = [compute_descriptor_release_set_layout];
+    let compute_descriptor_allocate_set_layouts = [compute_descriptor_allocate_set_layout];
+    let compute_descriptor_invalidate_set_layouts = [compute_descriptor_invalidate_set_layout];
+    let compute_descriptor_compact_set_layouts = [compute_descriptor_compact_set_layout];
+    let compute_descriptor_propagate_set_layout = device
+        .create_descriptor_set_layout(&descriptor_set_test_layout_create_info, None)
+        .unwrap();
+    let compute_descriptor_prepare_set_layouts = [compute_descriptor_prepare_set_layout];
+    let compute_descriptor_propagate_set_layouts = [compute_descriptor_propagate_set_layout];
+
+    let compute_pipeline_rebuild_layout_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants3>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let compute_pipeline_rebuild_layout_push_constant_ranges = [compute_pipeline_rebuild_layout_push_constant_range];
+
+    let compute_pipeline_release_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_release_set_layouts);
+    let compute_pipeline_allocate_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_allocate_set_layouts);
+    let compute_pipeline_invalidate_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_invalidate_set_layouts);
+    let compute_pipeline_compact_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_compact_set_layouts);
+    let compute_pipeline_prepare_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_prepare_set_layouts);
+    let compute_pipeline_propagate_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .push_constant_ranges(&compute_pipeline_rebuild_layout_push_constant_ranges)
+        .flags(vk::PipelineLayoutCreateFlags::empty())
+        .set_layouts(&compute_descriptor_propagate_set_layouts);
+
+    let compute_pipeline_release_layout = device
+        .create_pipeline_layout(&compute_pipeline_release_layout_create_info, None)
+        .unwrap();
+    let compute_pipeline_allocate_layout = device
+        .create_pipeline_layout(&compute_pipeline_allocate_layout_create_info, None)
+        .unwrap();
+    let compute_pipeline_invalidate_layout = device
+        .create_pipeline_layout(&compute_pipeline_invalidate_layout_create_info, None)
+        .unwrap();
+    let compute_pipeline_compact_layout = device
+        .create_pipeline_layout(&compute_pipeline_compact_layout_create_info, None)
+        .unwrap();
+    let compute_pipeline_prepare_layout = device
+        .create_pipeline_layout(&compute_pipeline_prepare_layout_create_info, None)
+        .unwrap();
+    let compute_pipeline_propagate_layout = device
+        .create_pipeline_layout(&compute_pipeline_propagate_layout_create_info, None)
+        .unwrap();
+
+    let compute_pipeline_release_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_release_layout)
+        .stage(compute_release_stage_create_info);
+    let compute_pipeline_allocate_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_allocate_layout)
+        .stage(compute_allocate_stage_create_info);
+    let compute_pipeline_invalidate_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_invalidate_layout)
+        .stage(compute_invalidate_stage_create_info);
+    let compute_pipeline_compact_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_compact_layout)
+        .stage(compute_compact_stage_create_info);
+    let compute_pipeline_prepare_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_prepare_layout)
+        .stage(compute_prepare_stage_create_info);
+    let compute_pipeline_propagate_create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(compute_pipeline_propagate_layout)
+        .stage(compute_propagate_stage_create_info);
+
     let compute_pipelines = device
         .create_compute_pipelines(
             vk::PipelineCache::null(),
-            &[compute_pipeline_init_create_info, compute_pipeline_test_create_info],
+            &[
+                compute_pipeline_init_create_info,
+                compute_pipeline_test_create_info,
+                compute_pipeline_release_create_info,
+                compute_pipeline_allocate_create_info,
+                compute_pipeline_invalidate_create_info,
+                compute_pipeline_compact_create_info,
+                compute_pipeline_prepare_create_info,
+                compute_pipeline_propagate_create_info,
+            ],
             None,
         )
         .unwrap();
 
     let first = (compute_descriptor_set_layout, compute_pipeline_layout, compute_pipelines[0]);
     let second = (compute_descriptor_test_set_layout, compute_pipeline_test_layout, compute_pipelines[1]);
+    let third = (compute_descriptor_release_set_layout, compute_pipeline_release_layout, compute_pipelines[2]);
+    let fourth = (compute_descriptor_allocate_set_layout, compute_pipeline_allocate_layout, compute_pipelines[3]);
+    let fifth = (compute_descriptor_invalidate_set_layout, compute_pipeline_invalidate_layout, compute_pipelines[4]);
+    let sixth = (compute_descriptor_compact_set_layout, compute_pipeline_compact_layout, compute_pipelines[5]);
+    let seventh = (compute_descriptor_prepare_set_layout, compute_pipeline_prepare_layout, compute_pipelines[6]);
+    let eighth = (compute_descriptor_propagate_set_layout, compute_pipeline_propagate_layout, compute_pipelines[7]);
     
-    let compute_pipeline = compute_pipelines[0];
-    (compute_shader_module,[first, second])
-}
\ No newline at end of file
+    (compute_shader_module,[first, second, third, fourth, fifth, sixth, seventh, eighth])
+}
+pub unsafe fn create_post_pipelines(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (vk::ShaderModule, [(
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+); 9]) {
+    let post_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let post_shader_module = device
+        .create_shader_module(&post_shader_module_create_info, None)
+        .unwrap();
+
+    let post_descriptor_set_layout_binding_hdr_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(0)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+    let post_descriptor_set_layout_binding_display_image = vk::DescriptorSetLayoutBinding::default()
+        .binding(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1);
+    let post_descriptor_set_layout_binding_histogram_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(2)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let post_descriptor_set_layout_binding_exposure_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(3)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let post_descriptor_set_layout_binding_bloom_images = vk::DescriptorSetLayoutBinding::default()
+        .binding(4)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(crate::post::BLOOM_MIPS);
+    let post_descriptor_set_layout_binding_lut_buffer = vk::DescriptorSetLayoutBinding::default()
+        .binding(5)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let post_descriptor_set_layout_bindings = [
+        post_descriptor_set_layout_binding_hdr_image,
+        post_descriptor_set_layout_binding_display_image,
+        post_descriptor_set_layout_binding_histogram_buffer,
+        post_descriptor_set_layout_binding_exposure_buffer,
+        post_descriptor_set_layout_binding_bloom_images,
+        post_descriptor_set_layout_binding_lut_buffer,
+    ];
+
+    let post_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&post_descriptor_set_layout_bindings);
+
+    let post_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants4>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let post_push_constant_ranges = [post_push_constant_range];
+
+    // Every kernel gets its own copy of the layouts so we can destroy them uniformly
+    let layouts = POST_KERNELS.map(|_| {
+        let descriptor_set_layout = device
+            .create_descriptor_set_layout(&post_descriptor_set_layout_create_info, None)
+            .unwrap();
+        let descriptor_set_layouts = [descriptor_set_layout];
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .push_constant_ranges(&post_push_constant_ranges)
+            .flags(vk::PipelineLayoutCreateFlags::empty())
+            .set_layouts(&descriptor_set_layouts);
+        let pipeline_layout = device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .unwrap();
+
+        (descriptor_set_layout, pipeline_layout)
+    });
+
+    let post_pipeline_create_infos = POST_KERNELS
+        .iter()
+        .zip(layouts.iter())
+        .map(|(name, (_, pipeline_layout))| {
+            let stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+                .flags(vk::PipelineShaderStageCreateFlags::empty())
+                .name(name)
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(post_shader_module);
+
+            vk::ComputePipelineCreateInfo::default()
+                .layout(*pipeline_layout)
+                .stage(stage_create_info)
+        })
+        .collect::<Vec<_>>();
+
+    let post_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &post_pipeline_create_infos,
+            None,
+        )
+        .unwrap();
+
+    let pipelines = std::array::from_fn(|i| (layouts[i].0, layouts[i].1, post_pipelines[i]));
+
+    (post_shader_module, pipelines)
+}
+
+pub unsafe fn create_denoise_pipelines(
+    raw: &[u32],
+    device: &ash::Device,
+) -> (vk::ShaderModule, [(
+    vk::DescriptorSetLayout,
+    vk::PipelineLayout,
+    vk::Pipeline,
+); 4]) {
+    let denoise_shader_module_create_info = vk::ShaderModuleCreateInfo::default()
+        .code(raw)
+        .flags(vk::ShaderModuleCreateFlags::empty());
+    let denoise_shader_module = device
+        .create_shader_module(&denoise_shader_module_create_info, None)
+        .unwrap();
+
+    // Illumination, albedo, depth, normal and motion, then the ping-ponged history and filter images and the output
+    let denoise_descriptor_set_layout_bindings = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9].map(|binding| {
+        let ping_pong = (5..=8).contains(&binding);
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(if ping_pong { 2 } else { 1 })
+    });
+
+    let denoise_descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+        .flags(vk::DescriptorSetLayoutCreateFlags::empty())
+        .bindings(&denoise_descriptor_set_layout_bindings);
+
+    let denoise_push_constant_range = vk::PushConstantRange::default()
+        .offset(0)
+        .size(size_of::<PushConstants7>() as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    let denoise_push_constant_ranges = [denoise_push_constant_range];
+
+    // Every kernel gets its own copy of the layouts so we can destroy them uniformly
+    let layouts = DENOISE_KERNELS.map(|_| {
+        let descriptor_set_layout = device
+            .create_descriptor_set_layout(&denoise_descriptor_set_layout_create_info, None)
+            .unwrap();
+        let descriptor_set_layouts = [descriptor_set_layout];
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .push_constant_ranges(&denoise_push_constant_ranges)
+            .flags(vk::PipelineLayoutCreateFlags::empty())
+            .set_layouts(&descriptor_set_layouts);
+        let pipeline_layout = device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .unwrap();
+
+        (descriptor_set_layout, pipeline_layout)
+    });
+
+    let denoise_pipeline_create_infos = DENOISE_KERNELS
+        .iter()
+        .zip(layouts.iter())
+        .map(|(name, (_, pipeline_layout))| {
+            let stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+                .flags(vk::PipelineShaderStageCreateFlags::empty())
+                .name(name)
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(denoise_shader_module);
+
+            vk::ComputePipelineCreateInfo::default()
+                .layout(*pipeline_layout)
+                .stage(stage_create_info)
+        })
+        .collect::<Vec<_>>();
+
+    let denoise_pipelines = device
+        .create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &denoise_pipeline_create_infos,
+            None,
+        )
+        .unwrap();
+
+    let pipelines = std::array::from_fn(|i| (layouts[i].0, layouts[i].1, denoise_pipelines[i]));
+
+    (denoise_shader_module, pipelines)
+}
@@ -1,17 +1,81 @@
 use ash::vk;
 
+// Number of descriptors of each type in one of the sets we allocate from the pool
+// Must match the layouts created in pipeline.rs
+#[derive(Clone, Copy)]
+struct SetDescriptors {
+    images: u32,
+    buffers: u32,
+    samplers: u32,
+}
+
+impl SetDescriptors {
+    const fn add(self, other: Self) -> Self {
+        Self {
+            images: self.images + other.images,
+            buffers: self.buffers + other.buffers,
+            samplers: self.samplers + other.samplers,
+        }
+    }
+
+    const fn max(self, other: Self) -> Self {
+        Self {
+            images: if self.images > other.images { self.images } else { other.images },
+            buffers: if self.buffers > other.buffers { self.buffers } else { other.buffers },
+            samplers: if self.samplers > other.samplers { self.samplers } else { other.samplers },
+        }
+    }
+}
+
+// Voxel update kernels, only allocated on tick frames (the generate set at startup is a subset of it)
+const VOXEL_SET: SetDescriptors = SetDescriptors { images: 3, buffers: 7, samplers: 0 };
+
+// Cloud shadow map and the cloud settings
+const CLOUD_SHADOW_SET: SetDescriptors = SetDescriptors { images: 1, buffers: 1, samplers: 0 };
+
+// Fog ambient output and the environment settings
+const FOG_AMBIENT_SET: SetDescriptors = SetDescriptors { images: 0, buffers: 2, samplers: 0 };
+
+// Raymarcher: output, voxels, surface indices, light, motion + 6 G-buffer images and the cloud shadows
+// then the surface data, materials, environment, clouds, view and fog ambient, and the block textures and environment map
+const RENDER_SET: SetDescriptors = SetDescriptors { images: 12, buffers: 6, samplers: 2 };
+
+// Output, voxels, accumulation and cloud shadows, point lights, materials, environment and clouds, block textures and environment map
+const REFERENCE_SET: SetDescriptors = SetDescriptors { images: 4, buffers: 4, samplers: 2 };
+
+// Current color, motion, history and resolved images
+const TAA_SET: SetDescriptors = SetDescriptors { images: 4, buffers: 0, samplers: 0 };
+
+// 6 single images and 4 ping-ponged pairs
+const DENOISE_SET: SetDescriptors = SetDescriptors { images: 14, buffers: 0, samplers: 0 };
+
+// Input, output and the bloom mips, then the histogram, exposure and LUT buffers
+const POST_SET: SetDescriptors = SetDescriptors { images: 2 + crate::post::BLOOM_MIPS, buffers: 3, samplers: 0 };
+
+// Everything that can be alive at the same time during a frame. The reference mode replaces the TAA and denoiser sets
+const FRAME_SETS: u32 = 7;
+const FRAME_DESCRIPTORS: SetDescriptors = VOXEL_SET
+    .add(CLOUD_SHADOW_SET)
+    .add(FOG_AMBIENT_SET)
+    .add(RENDER_SET)
+    .add(REFERENCE_SET.max(TAA_SET.add(DENOISE_SET)))
+    .add(POST_SET);
+
 pub unsafe fn create_descriptor_pool(device: &ash::Device) -> vk::DescriptorPool {
     let images = vk::DescriptorPoolSize::default()
-        .descriptor_count(5)
+        .descriptor_count(FRAME_DESCRIPTORS.images)
         .ty(vk::DescriptorType::STORAGE_IMAGE);
     let buffers = vk::DescriptorPoolSize::default()
-        .descriptor_count(3)
+        .descriptor_count(FRAME_DESCRIPTORS.buffers)
         .ty(vk::DescriptorType::STORAGE_BUFFER);
-    let descriptor_pool_sizes = [images, buffers];
+    let samplers = vk::DescriptorPoolSize::default()
+        .descriptor_count(FRAME_DESCRIPTORS.samplers)
+        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER);
+    let descriptor_pool_sizes = [images, buffers, samplers];
 
     let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
         .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
-        .max_sets(2)
+        .max_sets(FRAME_SETS)
         .pool_sizes(&descriptor_pool_sizes);
 
     let descriptor_pool = device
@@ -0,0 +1,641 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::{pipeline::{self, PushConstants4}, swapchain};
+
+// Number of bins in the luminance histogram. Must match HISTOGRAM_BINS in post.slang
+pub const HISTOGRAM_BINS: usize = 256;
+
+// Max number of mips in the bloom chain. Must match BLOOM_MIPS in post.slang
+pub const BLOOM_MIPS: u32 = 6;
+
+// Color grading LUT that gets used when COLOR_GRADING_LUT is not set
+pub const DEFAULT_LUT_PATH: &str = "grading.cube";
+
+// Must match the TONEMAPPER_* constants in post.slang
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    Aces = 0,
+    AgX = 1,
+    Reinhard = 2,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::AgX,
+            Tonemapper::AgX => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+        }
+    }
+}
+
+// Effects that run on the tonemapped image, in the order given by PostSettings::chain
+// Bloom is not in there since it has to happen before tonemapping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    ColorGrading,
+    Vignette,
+    FilmGrain,
+}
+
+impl PostEffect {
+    fn kernel(self) -> usize {
+        match self {
+            PostEffect::ColorGrading => pipeline::POST_GRADE,
+            PostEffect::Vignette => pipeline::POST_VIGNETTE,
+            PostEffect::FilmGrain => pipeline::POST_GRAIN,
+        }
+    }
+}
+
+pub struct BloomSettings {
+    pub enabled: bool,
+
+    // Exposed brightness where the bloom starts to kick in (with a soft knee)
+    pub threshold: f32,
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1.0,
+            intensity: 0.08,
+        }
+    }
+}
+
+pub struct ColorGradingSettings {
+    pub enabled: bool,
+
+    // Blend between the original and the graded color
+    pub strength: f32,
+}
+
+impl Default for ColorGradingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strength: 1.0,
+        }
+    }
+}
+
+pub struct VignetteSettings {
+    pub enabled: bool,
+    pub intensity: f32,
+
+    // How far from the corners the darkening starts (0 to 1)
+    pub smoothness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.35,
+            smoothness: 0.6,
+        }
+    }
+}
+
+pub struct FilmGrainSettings {
+    pub enabled: bool,
+    pub intensity: f32,
+}
+
+impl Default for FilmGrainSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.04,
+        }
+    }
+}
+
+// Controls the histogram based auto exposure, the tonemapping and the post effects
+pub struct PostSettings {
+    pub tonemapper: Tonemapper,
+
+    // Falls back to the manual exposure when disabled
+    pub auto_exposure: bool,
+    pub manual_exposure: f32,
+
+    // In stops, applied on top of both the auto and manual exposure
+    pub exposure_compensation: f32,
+
+    // Range of log2 luminance covered by the histogram
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+
+    // How fast the exposure adapts to the scene
+    pub adaptation_speed: f32,
+
+    pub bloom: BloomSettings,
+    pub color_grading: ColorGradingSettings,
+    pub vignette: VignetteSettings,
+    pub film_grain: FilmGrainSettings,
+
+    // Order in which the effects get applied after tonemapping
+    pub chain: Vec<PostEffect>,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            auto_exposure: true,
+            manual_exposure: 1.3,
+            exposure_compensation: 0.0,
+            min_log_luminance: -10.0,
+            max_log_luminance: 4.0,
+            adaptation_speed: 1.5,
+            bloom: BloomSettings::default(),
+            color_grading: ColorGradingSettings::default(),
+            vignette: VignetteSettings::default(),
+            film_grain: FilmGrainSettings::default(),
+            chain: vec![PostEffect::ColorGrading, PostEffect::Vignette, PostEffect::FilmGrain],
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn enabled(&self, effect: PostEffect) -> bool {
+        match effect {
+            PostEffect::ColorGrading => self.color_grading.enabled,
+            PostEffect::Vignette => self.vignette.enabled,
+            PostEffect::FilmGrain => self.film_grain.enabled,
+        }
+    }
+
+    pub fn push_constants(&self, resolution: vek::Vec2<u32>, delta: f32, lut_size: u32, frame: u32) -> PushConstants4 {
+        PushConstants4 {
+            resolution,
+            min_log_luminance: self.min_log_luminance,
+            log_luminance_range: (self.max_log_luminance - self.min_log_luminance).max(f32::EPSILON),
+            delta,
+            adaptation_speed: self.adaptation_speed,
+            exposure_compensation: self.exposure_compensation,
+            auto_exposure: self.auto_exposure as u32,
+            manual_exposure: self.manual_exposure,
+            tonemapper: self.tonemapper as u32,
+            bloom_threshold: self.bloom.threshold,
+            bloom_intensity: if self.bloom.enabled { self.bloom.intensity } else { 0.0 },
+            bloom_mip: 0,
+            lut_size,
+            lut_strength: self.color_grading.strength,
+            vignette_intensity: self.vignette.intensity,
+            vignette_smoothness: self.vignette.smoothness,
+            grain_intensity: self.film_grain.intensity,
+            frame,
+        }
+    }
+}
+
+// 3D LUT in the .cube format, red changes the fastest
+pub struct ColorGradingLut {
+    pub size: u32,
+    pub texels: Vec<vek::Vec4<f32>>,
+}
+
+impl ColorGradingLut {
+    // Maps every color to itself. A size of 2 is already exact since the lookup is trilinear
+    pub fn identity(size: u32) -> Self {
+        let size = size.max(2);
+        let max = (size - 1) as f32;
+        let texels = (0..size.pow(3))
+            .map(|i| vek::Vec4::new(i % size, (i / size) % size, i / (size * size), 0).map(|x| x as f32 / max).with_w(1.0))
+            .collect();
+        Self { size, texels }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut texels = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap();
+            match first {
+                "TITLE" => {},
+                "LUT_3D_SIZE" => {
+                    let value = words.next().and_then(|x| x.parse::<u32>().ok()).ok_or(format!("invalid LUT_3D_SIZE on line {}", number + 1))?;
+                    size = Some(value);
+                },
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".to_string()),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let values = words.filter_map(|x| x.parse::<f32>().ok()).collect::<Vec<_>>();
+                    let default = if first == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if values.iter().any(|&x| x != default) {
+                        log::warn!("{} is not supported, the LUT will be sampled over [0, 1]", first);
+                    }
+                },
+                _ => {
+                    let values = line.split_whitespace().map(|x| x.parse::<f32>()).collect::<Result<Vec<_>, _>>();
+                    match values.as_deref() {
+                        Ok(&[r, g, b]) => texels.push(vek::Vec4::new(r, g, b, 1.0)),
+                        _ => return Err(format!("invalid entry on line {}", number + 1)),
+                    }
+                },
+            }
+        }
+
+        let size = size.ok_or("missing LUT_3D_SIZE".to_string())?;
+        if size < 2 {
+            return Err(format!("LUT size must be at least 2, got {}", size));
+        }
+
+        if texels.len() != size.pow(3) as usize {
+            return Err(format!("expected {} entries, got {}", size.pow(3), texels.len()));
+        }
+
+        Ok(Self { size, texels })
+    }
+
+    // Loads the LUT from COLOR_GRADING_LUT (or the default path), falls back to the identity LUT
+    pub fn load() -> Self {
+        let path = std::env::var("COLOR_GRADING_LUT").ok();
+        let explicit = path.is_some();
+        let path = path.unwrap_or(DEFAULT_LUT_PATH.to_string());
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                if explicit {
+                    log::error!("could not read color grading LUT {}: {}", path, err);
+                }
+
+                return Self::identity(2);
+            }
+        };
+
+        match Self::parse(&text) {
+            Ok(lut) => {
+                log::info!("loaded {0}x{0}x{0} color grading LUT from {1}", lut.size, path);
+                lut
+            },
+            Err(err) => {
+                log::error!("could not parse color grading LUT {}: {}", path, err);
+                Self::identity(2)
+            },
+        }
+    }
+}
+
+pub fn bloom_extent(resolution: vek::Vec2<u32>) -> vek::Vec2<u32> {
+    (resolution / 2).map(|x| x.max(1))
+}
+
+pub fn bloom_mip_count(extent: vek::Vec2<u32>) -> u32 {
+    BLOOM_MIPS.min(extent.reduce_max().ilog2() + 1)
+}
+
+// Host visible so we can initialize it directly (and read the exposure back for the stats)
+unsafe fn create_post_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+    data: &[u8],
+    name: &'static str,
+    marker: &std::ffi::CStr,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(data.len() as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let mut allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name,
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(marker);
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    allocation.mapped_slice_mut().unwrap()[..data.len()].copy_from_slice(data);
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, allocation.offset()).unwrap();
+    (buffer, allocation)
+}
+
+pub unsafe fn create_histogram_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let bins = [0u32; HISTOGRAM_BINS];
+    create_post_buffer(device, allocator, binder, bytemuck::cast_slice(&bins), "Histogram Buffer Allocation", c"histogram buffer")
+}
+
+// Starts at 0 so the first frame snaps to the target exposure instead of adapting to it
+pub unsafe fn create_exposure_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let exposure = [0f32];
+    create_post_buffer(device, allocator, binder, bytemuck::cast_slice(&exposure), "Exposure Buffer Allocation", c"exposure buffer")
+}
+
+pub unsafe fn create_lut_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+    lut: &ColorGradingLut,
+) -> (vk::Buffer, Allocation) {
+    create_post_buffer(device, allocator, binder, bytemuck::cast_slice(&lut.texels), "Color Grading LUT Buffer Allocation", c"color grading lut buffer")
+}
+
+// Mip chain used by the bloom, with one view per mip. Mip 0 is half the output resolution
+// Contents are only valid within a frame so it gets transitioned from UNDEFINED every frame
+pub unsafe fn create_bloom_image(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    extent: vk::Extent2D,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Image, Allocation, Vec<vk::ImageView>) {
+    let resolution = vek::Vec2::new(extent.width, extent.height);
+    let bloom_extent = bloom_extent(resolution);
+    let mips = bloom_mip_count(bloom_extent);
+
+    let image_create_info = vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width: bloom_extent.x,
+            height: bloom_extent.y,
+            depth: 1,
+        })
+        .format(swapchain::HDR_FORMAT)
+        .image_type(vk::ImageType::TYPE_2D)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .mip_levels(mips)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .usage(vk::ImageUsageFlags::STORAGE)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .array_layers(1);
+    let image = device.create_image(&image_create_info, None).unwrap();
+    let requirements = device.get_image_memory_requirements(image);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Bloom Image Allocation",
+            requirements: requirements,
+            linear: false,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedImage(image),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    device
+        .bind_image_memory(image, allocation.memory(), 0)
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(image)
+            .object_name(c"bloom image");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let views = (0..mips).map(|mip| {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(mip)
+            .level_count(1)
+            .layer_count(1);
+
+        let image_view_create_info = vk::ImageViewCreateInfo::default()
+            .components(vk::ComponentMapping::default())
+            .flags(vk::ImageViewCreateFlags::empty())
+            .format(swapchain::HDR_FORMAT)
+            .image(image)
+            .subresource_range(subresource_range)
+            .view_type(vk::ImageViewType::TYPE_2D);
+
+        device.create_image_view(&image_view_create_info, None).unwrap()
+    }).collect();
+
+    (image, allocation, views)
+}
+
+pub unsafe fn destroy_bloom_image(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    bloom_image: (vk::Image, Allocation, Vec<vk::ImageView>),
+) {
+    let (image, allocation, views) = bloom_image;
+    for view in views {
+        device.destroy_image_view(view, None);
+    }
+
+    device.destroy_image(image, None);
+    allocator.free(allocation).unwrap();
+}
+
+pub fn read_exposure(allocation: &Allocation) -> f32 {
+    let raw = &allocation.mapped_slice().unwrap()[..size_of::<f32>()];
+    bytemuck::pod_read_unaligned::<f32>(raw)
+}
+
+// Builds the histogram of the HDR image, adapts the exposure, adds the bloom, tonemaps into the display image
+// and then runs the rest of the effect chain on the display image
+pub unsafe fn post_process(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    hdr_image_view: vk::ImageView,
+    display_image_view: vk::ImageView,
+    histogram_buffer: vk::Buffer,
+    exposure_buffer: vk::Buffer,
+    lut_buffer: vk::Buffer,
+    bloom_image: vk::Image,
+    bloom_image_views: &[vk::ImageView],
+    pipelines: &[(vk::DescriptorSetLayout, vk::PipelineLayout, vk::Pipeline)],
+    settings: &PostSettings,
+    push_constants: PushConstants4,
+) -> vk::DescriptorSet {
+    let layouts = [pipelines[pipeline::POST_HISTOGRAM].0];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let descriptor_hdr_image_info = vk::DescriptorImageInfo::default()
+        .image_view(hdr_image_view)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .sampler(vk::Sampler::null());
+    let descriptor_hdr_image_infos = [descriptor_hdr_image_info];
+
+    let descriptor_display_image_info = vk::DescriptorImageInfo::default()
+        .image_view(display_image_view)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .sampler(vk::Sampler::null());
+    let descriptor_display_image_infos = [descriptor_display_image_info];
+
+    let descriptor_histogram_buffer_info = vk::DescriptorBufferInfo::default()
+        .buffer(histogram_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_histogram_buffer_infos = [descriptor_histogram_buffer_info];
+
+    let descriptor_exposure_buffer_info = vk::DescriptorBufferInfo::default()
+        .buffer(exposure_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_exposure_buffer_infos = [descriptor_exposure_buffer_info];
+
+    // Small windows have less mips than the shader expects, the extra slots point to the last mip
+    let descriptor_bloom_image_infos = (0..BLOOM_MIPS as usize).map(|mip| {
+        vk::DescriptorImageInfo::default()
+            .image_view(bloom_image_views[mip.min(bloom_image_views.len() - 1)])
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null())
+    }).collect::<Vec<_>>();
+
+    let descriptor_lut_buffer_info = vk::DescriptorBufferInfo::default()
+        .buffer(lut_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_lut_buffer_infos = [descriptor_lut_buffer_info];
+
+    let descriptor_write_1 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .dst_binding(0)
+        .dst_set(descriptor_set)
+        .image_info(&descriptor_hdr_image_infos);
+    let descriptor_write_2 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .dst_binding(1)
+        .dst_set(descriptor_set)
+        .image_info(&descriptor_display_image_infos);
+    let descriptor_write_3 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(2)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_histogram_buffer_infos);
+    let descriptor_write_4 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(3)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_exposure_buffer_infos);
+    let descriptor_write_5 = vk::WriteDescriptorSet::default()
+        .descriptor_count(BLOOM_MIPS)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .dst_binding(4)
+        .dst_set(descriptor_set)
+        .image_info(&descriptor_bloom_image_infos);
+    let descriptor_write_6 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(5)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_lut_buffer_infos);
+
+    device.update_descriptor_sets(&[descriptor_write_1, descriptor_write_2, descriptor_write_3, descriptor_write_4, descriptor_write_5, descriptor_write_6], &[]);
+
+    let subresource_range = vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(vk::REMAINING_MIP_LEVELS)
+        .layer_count(1);
+    let bloom_undefined_to_general = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_access_mask(vk::AccessFlags2::NONE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .image(bloom_image)
+        .subresource_range(subresource_range);
+    let image_memory_barriers = [bloom_undefined_to_general];
+    let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    let dispatch = |kernel: usize, push_constants: &PushConstants4, groups: vek::Vec2<u32>| {
+        let (_, pipeline_layout, pipeline) = pipelines[kernel];
+
+        // Each pass reads what the previous one wrote (starting with the TAA output)
+        let barrier = vk::MemoryBarrier2::default()
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+        let barriers = [barrier];
+        let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+        device.cmd_pipeline_barrier2(cmd, &dep);
+
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &descriptor_sets,
+            &[],
+        );
+        device.cmd_bind_pipeline(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline,
+        );
+        device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(push_constants));
+        device.cmd_dispatch(cmd, groups.x, groups.y, 1);
+    };
+
+    let resolution = push_constants.resolution;
+    let full = (resolution + 31) / 32;
+    dispatch(pipeline::POST_HISTOGRAM, &push_constants, (resolution + 15) / 16);
+    dispatch(pipeline::POST_EXPOSURE, &push_constants, vek::Vec2::one());
+
+    // Downsample all the way to the smallest mip, then blur and accumulate back up to mip 0
+    if settings.bloom.enabled {
+        let bloom_extent = bloom_extent(resolution);
+        let mips = bloom_image_views.len() as u32;
+        let groups = |mip: u32| ((bloom_extent >> mip).map(|x| x.max(1)) + 7) / 8;
+
+        for mip in 0..mips {
+            let push_constants = PushConstants4 { bloom_mip: mip, ..push_constants };
+            dispatch(pipeline::POST_BLOOM_DOWNSAMPLE, &push_constants, groups(mip));
+        }
+
+        for mip in (0..mips.saturating_sub(1)).rev() {
+            let push_constants = PushConstants4 { bloom_mip: mip, ..push_constants };
+            dispatch(pipeline::POST_BLOOM_UPSAMPLE, &push_constants, groups(mip));
+        }
+    }
+
+    dispatch(pipeline::POST_TONEMAP, &push_constants, full);
+
+    for &effect in settings.chain.iter().filter(|&&effect| settings.enabled(effect)) {
+        dispatch(effect.kernel(), &push_constants, full);
+    }
+
+    dispatch(pipeline::POST_ENCODE, &push_constants, full);
+
+    descriptor_set
+}
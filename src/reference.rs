@@ -0,0 +1,181 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::{clouds::Clouds, image, lights::PointLight, pipeline::PushConstants6};
+
+// Sum of the path traced samples, needs the extra precision since it keeps growing
+pub const ACCUMULATION_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+// Controls the progressive path traced reference mode, which replaces the raymarcher and the TAA pass when enabled
+pub struct ReferenceSettings {
+    pub enabled: bool,
+
+    // Diffuse bounces after the first hit (glass and mirrors count too)
+    pub bounces: u32,
+
+    // Stops tracing new samples once we got this many, the image is converged enough by then
+    pub max_samples: u32,
+}
+
+impl Default for ReferenceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bounces: 4,
+            max_samples: 4096,
+        }
+    }
+}
+
+// Tracks what the accumulated samples were traced with, so we know when they need to be thrown away
+#[derive(Default)]
+pub struct Accumulation {
+    pub samples: u32,
+    view_proj: vek::Mat4<f32>,
+    position: vek::Vec4<f32>,
+    sun: vek::Vec4<f32>,
+    clouds: Clouds,
+    lights: Vec<PointLight>,
+}
+
+impl Accumulation {
+    // Needed whenever the scene changes in a way we can't detect here (voxel edits, resizing)
+    pub fn reset(&mut self) {
+        self.samples = 0;
+    }
+
+    // Called once per frame before tracing, resets the samples if the camera, the sun, the clouds or the lights changed
+    // The clouds and lights get compared byte by byte so moving or recoloring anything counts too
+    pub fn update(&mut self, view_proj: vek::Mat4<f32>, position: vek::Vec4<f32>, sun: vek::Vec4<f32>, clouds: &Clouds, lights: &[PointLight]) {
+        let clouds_changed = bytemuck::bytes_of(clouds) != bytemuck::bytes_of(&self.clouds);
+        let lights_changed = bytemuck::cast_slice::<PointLight, u8>(lights) != bytemuck::cast_slice::<PointLight, u8>(&self.lights);
+        if view_proj != self.view_proj || position != self.position || sun != self.sun || clouds_changed || lights_changed {
+            self.samples = 0;
+        }
+
+        self.view_proj = view_proj;
+        self.position = position;
+        self.sun = sun;
+        self.clouds = *clouds;
+        self.lights.clear();
+        self.lights.extend_from_slice(lights);
+    }
+}
+
+// Same size as the window since the reference mode doesn't use the dynamic resolution
+pub unsafe fn create_accumulation_image(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    extent: vek::Vec2<u32>,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Image, Allocation, vk::ImageView) {
+    image::create_storage_image(device, allocator, extent, ACCUMULATION_FORMAT, binder, c"reference accumulation image")
+}
+
+// Traces one more sample per pixel (unless we got enough of them) and writes the average to the output image
+pub unsafe fn trace(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    output_image_view: vk::ImageView,
+    voxel_image_view: vk::ImageView,
+    accumulation_image_view: vk::ImageView,
+    point_light_buffer: vk::Buffer,
+    material_buffer: vk::Buffer,
+    environment_buffer: vk::Buffer,
+    cloud_buffer: vk::Buffer,
+    cloud_shadow_image_view: vk::ImageView,
+    block_textures: (vk::ImageView, vk::Sampler),
+    environment_map: (vk::ImageView, vk::Sampler),
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    push_constants: PushConstants6,
+) -> vk::DescriptorSet {
+    let layouts = [descriptor_set_layout];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let views = [output_image_view, voxel_image_view, accumulation_image_view, cloud_shadow_image_view];
+    let descriptor_image_infos = views.map(|view| {
+        [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null())]
+    });
+    let descriptor_buffer_infos = [point_light_buffer, material_buffer, environment_buffer, cloud_buffer].map(|buffer| {
+        [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(0)
+            .range(u64::MAX)]
+    });
+
+    let mut descriptor_writes = [0, 1, 2, 9].iter().zip(&descriptor_image_infos).map(|(binding, infos)| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(*binding)
+            .dst_set(descriptor_set)
+            .image_info(infos)
+    }).collect::<Vec<_>>();
+    descriptor_writes.extend([3, 4, 7, 8].iter().zip(&descriptor_buffer_infos).map(|(binding, infos)| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .dst_binding(*binding)
+            .dst_set(descriptor_set)
+            .buffer_info(infos)
+    }));
+
+    let descriptor_sampled_image_infos = [block_textures, environment_map].map(|(view, sampler)| {
+        [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(sampler)]
+    });
+    descriptor_writes.extend([5, 6].iter().zip(&descriptor_sampled_image_infos).map(|(binding, infos)| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .dst_binding(*binding)
+            .dst_set(descriptor_set)
+            .image_info(infos)
+    }));
+
+    device.update_descriptor_sets(&descriptor_writes, &[]);
+
+    // Wait for the voxel update (and for the post pass of the last frame that read the output)
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    device.cmd_bind_descriptor_sets(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &descriptor_sets,
+        &[],
+    );
+    device.cmd_bind_pipeline(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline,
+    );
+    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants));
+
+    let groups = (push_constants.resolution + 15) / 16;
+    device.cmd_dispatch(cmd, groups.x, groups.y, 1);
+
+    descriptor_set
+}
@@ -0,0 +1,118 @@
+use ash::vk;
+
+// How many timestamps we write every frame (start and end of the command buffer)
+const TIMESTAMPS: u32 = 2;
+
+// Scales the raymarcher resolution (per axis, relative to the window) to hit a target GPU frame time
+// The render targets are always allocated for max_scale and the raymarcher only fills a sub-rect of them
+pub struct DynamicResolution {
+    pub enabled: bool,
+
+    // Current scale, changes every frame when enabled
+    pub scale: f32,
+
+    // Scale used when the dynamic resolution is disabled
+    pub fixed_scale: f32,
+
+    pub min_scale: f32,
+    pub max_scale: f32,
+
+    // GPU frame time we try to stay at (in seconds)
+    pub target_frame_time: f32,
+
+    // Smoothed GPU frame time (in seconds), 0 until we got the first measurement
+    pub gpu_frame_time: f32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scale: 0.5,
+            fixed_scale: 0.5,
+            min_scale: 0.25,
+            max_scale: 1.0,
+            target_frame_time: 1.0 / 60.0,
+            gpu_frame_time: 0.0,
+        }
+    }
+}
+
+impl DynamicResolution {
+    // Called once per frame with the measured GPU time of the last frame (in seconds)
+    pub fn update(&mut self, gpu_time: Option<f32>) {
+        if let Some(gpu_time) = gpu_time {
+            self.gpu_frame_time = if self.gpu_frame_time > 0.0 {
+                self.gpu_frame_time + (gpu_time - self.gpu_frame_time) * 0.1
+            } else {
+                gpu_time
+            };
+        }
+
+        if !self.enabled || self.gpu_frame_time <= 0.0 {
+            self.scale = self.fixed_scale.clamp(self.min_scale, self.max_scale);
+            return;
+        }
+
+        // GPU time scales with the pixel count, which goes with the square of the scale
+        // Only move part of the way there every frame so it doesn't oscillate
+        let ratio = self.target_frame_time / self.gpu_frame_time;
+
+        // Small dead zone so the resolution doesn't keep moving when we're close enough
+        if (ratio - 1.0).abs() < 0.05 {
+            return;
+        }
+
+        let target = self.scale * ratio.sqrt();
+        let step = ((target - self.scale) * 0.1).clamp(-0.02, 0.02);
+        self.scale = (self.scale + step).clamp(self.min_scale, self.max_scale);
+    }
+
+    // Size of the sub-rect the raymarcher renders into
+    pub fn render_resolution(&self, output: vek::Vec2<u32>) -> vek::Vec2<u32> {
+        output
+            .map(|x| (x as f32 * self.scale).round() as u32)
+            .map2(max_render_resolution(output, self.max_scale), |x, max| x.clamp(1, max))
+    }
+}
+
+// What the render targets are allocated for
+pub fn max_render_resolution(output: vek::Vec2<u32>, max_scale: f32) -> vek::Vec2<u32> {
+    output.map(|x| ((x as f32 * max_scale).ceil() as u32).max(1))
+}
+
+// Returns None if the queue can't write timestamps
+pub unsafe fn create_timestamp_query_pool(
+    device: &ash::Device,
+    timestamp_valid_bits: u32,
+) -> Option<vk::QueryPool> {
+    if timestamp_valid_bits == 0 {
+        log::warn!("queue does not support timestamps, dynamic resolution will stay at its fixed scale");
+        return None;
+    }
+
+    let query_pool_create_info = vk::QueryPoolCreateInfo::default()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(TIMESTAMPS);
+    Some(device.create_query_pool(&query_pool_create_info, None).unwrap())
+}
+
+pub unsafe fn write_start_timestamp(device: &ash::Device, cmd: vk::CommandBuffer, pool: vk::QueryPool) {
+    device.cmd_reset_query_pool(cmd, pool, 0, TIMESTAMPS);
+    device.cmd_write_timestamp2(cmd, vk::PipelineStageFlags2::TOP_OF_PIPE, pool, 0);
+}
+
+pub unsafe fn write_end_timestamp(device: &ash::Device, cmd: vk::CommandBuffer, pool: vk::QueryPool) {
+    device.cmd_write_timestamp2(cmd, vk::PipelineStageFlags2::BOTTOM_OF_PIPE, pool, 1);
+}
+
+// Must be called after the frame's fence got signaled. Returns the GPU time in seconds
+pub unsafe fn read_gpu_time(device: &ash::Device, pool: vk::QueryPool, timestamp_period: f32) -> Option<f32> {
+    let mut timestamps = [0u64; TIMESTAMPS as usize];
+    device
+        .get_query_pool_results(pool, 0, &mut timestamps, vk::QueryResultFlags::TYPE_64)
+        .ok()?;
+
+    let ticks = timestamps[1].checked_sub(timestamps[0])?;
+    Some(ticks as f32 * timestamp_period * 1e-9)
+}
@@ -0,0 +1,70 @@
+use std::fmt::Display;
+
+// How often the stats get written to the window title (in seconds)
+const REFRESH_INTERVAL: f32 = 1.0;
+
+#[derive(Default)]
+pub struct Stats {
+    pub frame_time: f32,
+    pub gpu_frame_time: f32,
+    pub render_scale: f32,
+    pub surface_faces_used: u32,
+    pub surface_faces_capacity: u32,
+    pub surface_voxels: u32,
+    pub shadow_texels: u32,
+    pub shadow_texel_budget: u32,
+    pub exposure: f32,
+
+    // Only set while the path traced reference is shown
+    pub reference_samples: Option<u32>,
+
+    accumulator: f32,
+    frames: u32,
+}
+
+impl Stats {
+    // Returns true whenever the stats should be displayed again
+    pub fn update(&mut self, delta: f32) -> bool {
+        self.accumulator += delta;
+        self.frames += 1;
+
+        if self.accumulator > REFRESH_INTERVAL {
+            self.frame_time = self.accumulator / self.frames as f32;
+            self.accumulator = 0f32;
+            self.frames = 0;
+            return true;
+        }
+
+        return false;
+    }
+
+    pub fn surface_utilization(&self) -> f32 {
+        self.surface_faces_used as f32 / self.surface_faces_capacity.max(1) as f32
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2}ms ({:.0} fps) | gpu: {:.2}ms at {:.0}% scale | surface faces: {}/{} ({:.1}%) | surface voxels: {} | shadow texels: {}/{} | exposure: {:.2}",
+            self.frame_time * 1000f32,
+            1f32 / self.frame_time.max(f32::EPSILON),
+            self.gpu_frame_time * 1000f32,
+            self.render_scale * 100f32,
+            self.surface_faces_used,
+            self.surface_faces_capacity,
+            self.surface_utilization() * 100f32,
+            self.surface_voxels,
+            self.shadow_texels,
+            self.shadow_texel_budget,
+            self.exposure,
+        )?;
+
+        if let Some(samples) = self.reference_samples {
+            write!(f, " | reference samples: {}", samples)?;
+        }
+
+        Ok(())
+    }
+}
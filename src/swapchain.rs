@@ -25,6 +25,13 @@ pub unsafe fn create_swapchain(
     let surface_formats: Vec<vk::SurfaceFormatKHR> = surface_loader
         .get_physical_device_surface_formats(physical_device, surface_khr)
         .unwrap();
+    // The post pass already encodes to sRGB, an _SRGB swapchain would encode it a second time
+    // get_physical_device_score makes sure one of these is available
+    let surface_format = surface_formats
+        .iter()
+        .copied()
+        .find(|x| matches!(x.format, vk::Format::B8G8R8A8_UNORM | vk::Format::R8G8B8A8_UNORM) && x.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+        .unwrap();
     let present = present_modes
         .iter()
         .copied()
@@ -33,7 +40,7 @@ pub unsafe fn create_swapchain(
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
         .surface(surface_khr)
         .min_image_count(surface_capabilities.min_image_count)
-        .image_format(surface_formats[0].format)
+        .image_format(surface_format.format)
         .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
         .image_extent(extent)
         .image_array_layers(1)
@@ -65,10 +72,11 @@ pub unsafe fn create_swapchain(
         }
     }
 
-    (swapchain_loader, swapchain, images, surface_formats[0].format)
+    (swapchain_loader, swapchain, images, surface_format.format)
 }
 
-pub const SCALING_FACTOR: u32 = 2;
+// Format of the images we raymarch and post process into before blitting to the swapchain
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
 
 pub unsafe fn create_temporary_target_render_image(
     instance: &ash::Instance,
@@ -79,20 +87,18 @@ pub unsafe fn create_temporary_target_render_image(
     allocator: &mut gpu_allocator::vulkan::Allocator,
     queue_family_index: u32,
     extent: vk::Extent2D,
+    format: vk::Format,
     binder: &Option<ash::ext::debug_utils::Device>,
     name: &CStr
 ) -> (vk::Image, gpu_allocator::vulkan::Allocation) {
     let queue_family_indices = [queue_family_index];
-    let surface_formats: Vec<vk::SurfaceFormatKHR> = surface_loader
-        .get_physical_device_surface_formats(physical_device, surface_khr)
-        .unwrap();
     let rt_image_create_info = vk::ImageCreateInfo::default()
         .extent(vk::Extent3D {
-            width: extent.width / SCALING_FACTOR,
-            height: extent.height / SCALING_FACTOR,
+            width: extent.width.max(1),
+            height: extent.height.max(1),
             depth: 1,
         })
-        .format(surface_formats[0].format)
+        .format(format)
         .image_type(vk::ImageType::TYPE_2D)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .mip_levels(1)
@@ -0,0 +1,178 @@
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::{image, pipeline::PushConstants5, swapchain};
+
+// Length of the Halton sequence used to jitter the rays before it repeats
+pub const JITTER_SAMPLES: u32 = 8;
+
+// Controls the temporal anti-aliasing and upscaling
+// When disabled the TAA pass still upscales the image, just without any jitter or history
+pub struct TaaSettings {
+    pub enabled: bool,
+
+    // How much of the history is kept every frame (0 to 1)
+    pub blend: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blend: 0.9,
+        }
+    }
+}
+
+impl TaaSettings {
+    // Sub-pixel offset of the rays for this frame, in render pixels between -0.5 and 0.5
+    pub fn jitter(&self, frame: u32) -> vek::Vec2<f32> {
+        if !self.enabled {
+            return vek::Vec2::zero();
+        }
+
+        // Skip index 0 since it's (0, 0) for every base
+        let index = frame % JITTER_SAMPLES + 1;
+        vek::Vec2::new(halton(index, 2), halton(index, 3)) - 0.5
+    }
+
+    pub fn blend(&self) -> f32 {
+        if self.enabled { self.blend } else { 0.0 }
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Inverse of the ray generation in raymarcher.slang (only the rotation and projection part of the matrix)
+// Gets passed as previous_rays next frame so the raymarcher can compute motion vectors
+pub fn reprojection_matrix(view_proj: vek::Mat4<f32>) -> vek::Mat4<f32> {
+    let mut rays = view_proj;
+    rays.cols.x.w = 0.0;
+    rays.cols.y.w = 0.0;
+    rays.cols.z.w = 0.0;
+    rays.cols.w = vek::Vec4::unit_w();
+    rays.inverted()
+}
+
+// Two full resolution images that we ping-pong between, one is the history and the other the output
+pub unsafe fn create_history_images(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    extent: vek::Vec2<u32>,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> [(vk::Image, Allocation, vk::ImageView); 2] {
+    [
+        image::create_storage_image(device, allocator, extent, swapchain::HDR_FORMAT, binder, c"taa history image 0"),
+        image::create_storage_image(device, allocator, extent, swapchain::HDR_FORMAT, binder, c"taa history image 1"),
+    ]
+}
+
+// Moves the TAA (and G-buffer) images out of UNDEFINED. Needs to happen before the raymarcher writes to them
+// Only done after the images got (re)created, which is also when the history gets reset
+pub unsafe fn transition_images(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    images: &[vk::Image],
+) {
+    let subresource_range = vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(1)
+        .layer_count(1);
+
+    let barriers = images.iter().map(|&image| {
+        vk::ImageMemoryBarrier2::default()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .image(image)
+            .subresource_range(subresource_range)
+    }).collect::<Vec<_>>();
+
+    let dep = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+}
+
+// Resolves the jittered raymarcher output and the history into the full resolution output image
+pub unsafe fn resolve(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    current_image_view: vk::ImageView,
+    motion_image_view: vk::ImageView,
+    history_image_view: vk::ImageView,
+    resolved_image_view: vk::ImageView,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    push_constants: PushConstants5,
+) -> vk::DescriptorSet {
+    let layouts = [descriptor_set_layout];
+    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&layouts);
+    let descriptor_sets = device
+        .allocate_descriptor_sets(&descriptor_set_allocate_info)
+        .unwrap();
+    let descriptor_set = descriptor_sets[0];
+
+    let views = [current_image_view, motion_image_view, history_image_view, resolved_image_view];
+    let descriptor_image_infos = views.map(|view| {
+        [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(vk::Sampler::null())]
+    });
+
+    let descriptor_writes = [0, 1, 2, 3].map(|binding| {
+        vk::WriteDescriptorSet::default()
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .dst_binding(binding as u32)
+            .dst_set(descriptor_set)
+            .image_info(&descriptor_image_infos[binding])
+    });
+
+    device.update_descriptor_sets(&descriptor_writes, &[]);
+
+    // Wait for the raymarcher (and for the post pass of the last frame that read the history)
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    device.cmd_bind_descriptor_sets(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &descriptor_sets,
+        &[],
+    );
+    device.cmd_bind_pipeline(
+        cmd,
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline,
+    );
+    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants));
+
+    let groups = (push_constants.output_resolution + 15) / 16;
+    device.cmd_dispatch(cmd, groups.x, groups.y, 1);
+
+    descriptor_set
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+use crate::image;
+
+// Directory the block textures get loaded from when BLOCK_TEXTURES is not set
+pub const DEFAULT_TEXTURE_DIRECTORY: &str = "textures";
+
+// Textures are authored in sRGB, the sampler gives us back linear colors
+pub const BLOCK_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+// Size of the generated textures used when the PNGs can't be loaded
+pub const FALLBACK_TEXTURE_SIZE: u32 = 16;
+
+// Layers of the texture array. Must match the values in lighting.slang
+pub const LAYER_DIRT: usize = 0;
+pub const LAYER_GRASS_TOP: usize = 1;
+pub const LAYER_GRASS_SIDE: usize = 2;
+pub const LAYER_PLANKS: usize = 3;
+
+// File of each layer, with the (linear) color of its fallback texture
+pub const BLOCK_TEXTURES: [(&str, [f32; 3]); 4] = [
+    ("dirt.png", [0.054, 0.044, 0.027]),
+    ("grass_top.png", [0.3, 0.3, 0.3]),
+    ("grass_side.png", [0.054, 0.044, 0.027]),
+    ("planks.png", [0.25, 0.16, 0.08]),
+];
+
+// How many rows of the fallback grass side texture are grass
+const FALLBACK_GRASS_ROWS: u32 = 2;
+
+// All the layers of the texture array as RGBA8, one after the other
+pub struct BlockTextures {
+    pub size: u32,
+    pub texels: Vec<u8>,
+}
+
+// Same hash as the one in other.slang
+fn hash(mut s: u32) -> u32 {
+    s ^= 2747636419;
+    s = s.wrapping_mul(2654435769);
+    s ^= s >> 16;
+    s = s.wrapping_mul(2654435769);
+    s ^= s >> 16;
+    s.wrapping_mul(2654435769)
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0).round() as u8
+}
+
+// Decodes a square PNG into RGBA8 texels
+fn decode(bytes: &[u8]) -> Result<(u32, Vec<u8>), String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|err| err.to_string())?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    if info.width != info.height {
+        return Err(format!("textures must be square, got {}x{}", info.width, info.height));
+    }
+
+    let texels = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes.chunks_exact(3).flat_map(|x| [x[0], x[1], x[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).flat_map(|x| [x[0], x[0], x[0], x[1]]).collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|x| [*x, *x, *x, 255]).collect(),
+        png::ColorType::Indexed => return Err("indexed colors did not get expanded".to_string()),
+    };
+
+    Ok((info.width, texels))
+}
+
+// Noisy flat color, close to what the surfaces looked like before we had textures
+fn fallback(layer: usize, size: u32) -> Vec<u8> {
+    let (_, color) = BLOCK_TEXTURES[layer];
+    let (_, grass) = BLOCK_TEXTURES[LAYER_GRASS_TOP];
+    let grass_rows = FALLBACK_GRASS_ROWS * size / FALLBACK_TEXTURE_SIZE;
+
+    (0..size * size).flat_map(|i| {
+        let row = i / size;
+        let color = if layer == LAYER_GRASS_SIDE && row < grass_rows { grass } else { color };
+        let mixer = (hash(i + hash(layer as u32)) as f32 / u32::MAX as f32) * 0.2 + 0.8;
+        [linear_to_srgb(color[0] * mixer), linear_to_srgb(color[1] * mixer), linear_to_srgb(color[2] * mixer), 255]
+    }).collect()
+}
+
+impl BlockTextures {
+    // Loads the PNGs from BLOCK_TEXTURES (or the default directory), missing or broken ones get replaced by a fallback
+    // Missing ones are expected since we don't ship any, only the broken ones are worth an error
+    // All the layers have to be the same size, the first texture that loads decides which one
+    pub fn load() -> Self {
+        let directory = std::env::var("BLOCK_TEXTURES").unwrap_or(DEFAULT_TEXTURE_DIRECTORY.to_string());
+
+        let decoded = BLOCK_TEXTURES.map(|(name, _)| {
+            let path = Path::new(&directory).join(name);
+            let result = match std::fs::read(&path) {
+                Ok(bytes) => decode(&bytes).map(Some),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.to_string()),
+            };
+
+            match result {
+                Ok(texture) => texture,
+                Err(err) => {
+                    log::error!("could not load block texture {}: {}", path.display(), err);
+                    None
+                }
+            }
+        });
+
+        let generated = decoded.iter().filter(|texture| texture.is_none()).count();
+        let size = decoded.iter().flatten().map(|(size, _)| *size).next().unwrap_or(FALLBACK_TEXTURE_SIZE);
+        let mut texels = Vec::<u8>::new();
+
+        for (layer, texture) in decoded.into_iter().enumerate() {
+            match texture {
+                Some((texture_size, layer_texels)) if texture_size == size => texels.extend(layer_texels),
+                Some((texture_size, _)) => {
+                    log::error!("block texture {} is {1}x{1} but the others are {2}x{2}", BLOCK_TEXTURES[layer].0, texture_size, size);
+                    texels.extend(fallback(layer, size));
+                }
+                None => texels.extend(fallback(layer, size)),
+            }
+        }
+
+        log::info!("loaded {} block textures of {1}x{1} ({2} generated) from {3}", BLOCK_TEXTURES.len(), size, generated, directory);
+        Self { size, texels }
+    }
+}
+
+// Nearest filtering to keep the pixel art crisp, the face UVs wrap around anyways
+pub unsafe fn create_block_texture_sampler(device: &ash::Device) -> vk::Sampler {
+    let sampler_create_info = vk::SamplerCreateInfo::default()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::REPEAT)
+        .address_mode_w(vk::SamplerAddressMode::REPEAT)
+        .max_lod(0.0);
+    device.create_sampler(&sampler_create_info, None).unwrap()
+}
+
+// 2D texture array with one layer per entry of BLOCK_TEXTURES
+pub unsafe fn create_block_texture_array(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    queue_family_index: u32,
+    pool: vk::CommandPool,
+    queue: vk::Queue,
+    textures: &BlockTextures,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Image, Allocation, vk::ImageView) {
+    image::create_sampled_image(
+        device,
+        allocator,
+        queue_family_index,
+        pool,
+        queue,
+        vek::Vec2::broadcast(textures.size),
+        BLOCK_TEXTURES.len() as u32,
+        BLOCK_TEXTURE_FORMAT,
+        vk::ImageViewType::TYPE_2D_ARRAY,
+        &textures.texels,
+        binder,
+        c"block texture array",
+    )
+}
@@ -0,0 +1,177 @@
+use std::f32::consts::TAU;
+
+// Number of days before the seasons loop around
+pub const DAYS_PER_YEAR: f32 = 365.0;
+
+// Strength of the moon light relative to the sun
+pub const MOON_INTENSITY: f32 = 0.08;
+
+// Where the time of day gets saved, relative to the working directory
+// The voxel world itself is regenerated on every launch, so this is the only thing that persists
+pub const TIME_OF_DAY_PATH: &str = "world.time";
+
+// Day/night cycle that drives the sun (and moon) direction
+// Advanced by the fixed-step ticker so it does not depend on the frame rate
+#[derive(Clone, Copy, Debug)]
+pub struct TimeOfDay {
+    // Fraction of the current day in [0, 1), 0 is midnight and 0.5 is noon
+    pub time: f32,
+
+    // Number of full days since the world was created
+    pub day: u32,
+
+    // Length of a full day in seconds
+    pub day_length: f32,
+
+    // Latitude of the voxel world and tilt of the planet axis (in radians)
+    pub latitude: f32,
+    pub axial_tilt: f32,
+
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            time: 0.35,
+            day: 0,
+            day_length: 600.0,
+            latitude: 45f32.to_radians(),
+            axial_tilt: 23.44f32.to_radians(),
+            paused: false,
+        }
+    }
+}
+
+// Raw data that gets written to disk, must stay Pod
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TimeOfDayData {
+    time: f32,
+    day: u32,
+    day_length: f32,
+    latitude: f32,
+    axial_tilt: f32,
+    paused: u32,
+}
+
+impl TimeOfDay {
+    // Called once per tick with the fixed tick duration
+    pub fn tick(&mut self, delta: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+
+        self.set_time(self.time + delta / self.day_length);
+    }
+
+    // Sets the time as a fraction of the day. Values outside [0, 1) roll over to the next (or previous) days
+    pub fn set_time(&mut self, time: f32) {
+        let days = time.floor();
+        self.day = (self.day as i64 + days as i64).max(0) as u32;
+        self.time = time - days;
+    }
+
+    // Sets the time in hours (0 to 24) without changing the current day
+    pub fn set_hours(&mut self, hours: f32) {
+        self.time = (hours / 24.0).rem_euclid(1.0);
+    }
+
+    pub fn skip_hours(&mut self, hours: f32) {
+        self.set_time(self.time + hours / 24.0);
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.time * 24.0
+    }
+
+    pub fn set_day_length(&mut self, seconds: f32) {
+        self.day_length = seconds.max(0.0);
+    }
+
+    // Sun declination over the year caused by the axial tilt
+    pub fn declination(&self) -> f32 {
+        let year = (self.day as f32 + self.time) / DAYS_PER_YEAR;
+        self.axial_tilt * (year * TAU).sin()
+    }
+
+    // Direction towards the sun (x: east, y: up, z: south)
+    pub fn sun_direction(&self) -> vek::Vec3<f32> {
+        let hour_angle = (self.time - 0.5) * TAU;
+        let declination = self.declination();
+
+        // Position of the sun relative to the equator, then rotated by the latitude around the east axis
+        let east = -declination.cos() * hour_angle.sin();
+        let equator = declination.cos() * hour_angle.cos();
+        let pole = declination.sin();
+
+        let up = self.latitude.cos() * equator + self.latitude.sin() * pole;
+        let north = -self.latitude.sin() * equator + self.latitude.cos() * pole;
+        vek::Vec3::new(east, up, -north).normalized()
+    }
+
+    // Always a full moon, right across from the sun
+    pub fn moon_direction(&self) -> vek::Vec3<f32> {
+        -self.sun_direction()
+    }
+
+    // Main directional light, the sun during the day and the moon at night
+    // xyz: direction towards the light, w: intensity
+    pub fn light(&self) -> vek::Vec4<f32> {
+        let sun = self.sun_direction();
+        if sun.y >= 0.0 {
+            sun.with_w(1.0)
+        } else {
+            self.moon_direction().with_w(MOON_INTENSITY)
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = TimeOfDayData {
+            time: self.time,
+            day: self.day,
+            day_length: self.day_length,
+            latitude: self.latitude,
+            axial_tilt: self.axial_tilt,
+            paused: self.paused as u32,
+        };
+
+        bytemuck::bytes_of(&data).to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let data = bytemuck::try_pod_read_unaligned::<TimeOfDayData>(bytes).ok()?;
+
+        // A NaN in there would propagate into the sun direction and break every shadow
+        let fields = [data.time, data.day_length, data.latitude, data.axial_tilt];
+        if fields.iter().any(|x| !x.is_finite()) || data.day_length < 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            time: data.time.rem_euclid(1.0),
+            day: data.day,
+            day_length: data.day_length,
+            latitude: data.latitude,
+            axial_tilt: data.axial_tilt,
+            paused: data.paused != 0,
+        })
+    }
+
+    // Falls back to the default time of day if there's no saved file (or if it's broken)
+    pub fn load() -> Self {
+        match std::fs::read(TIME_OF_DAY_PATH) {
+            Ok(bytes) => Self::from_bytes(&bytes).unwrap_or_else(|| {
+                log::warn!("could not parse {}, using the default time of day", TIME_OF_DAY_PATH);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = std::fs::write(TIME_OF_DAY_PATH, self.to_bytes()) {
+            log::error!("could not save the time of day: {}", err);
+        }
+    }
+}
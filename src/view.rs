@@ -0,0 +1,57 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::vulkan::{Allocation, Allocator};
+
+// Camera matrices of the raymarcher, too big to fit in the push constants with everything else
+// Must match View in raymarcher.slang
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct View {
+    pub view_proj: vek::Mat4<f32>,
+
+    // Camera of the last frame, used to compute the motion vectors (see taa::reprojection_matrix)
+    pub previous_rays: vek::Mat4<f32>,
+    pub previous_position: vek::Vec4<f32>,
+}
+
+// Host visible since it changes every frame
+pub unsafe fn create_view_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<View>() as u64);
+    let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "View Buffer Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"view buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+pub fn upload_view(allocation: &mut Allocation, view: &View) {
+    let raw = bytemuck::bytes_of(view);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+}
@@ -3,11 +3,399 @@ use std::ffi::CStr;
 use ash::vk;
 use gpu_allocator::vulkan::{Allocation, Allocator};
 
-use crate::pipeline::PushConstants2;
+use crate::pipeline::{PushConstants2, PushConstants3};
 
 pub const SIZE: u32 = 64;
 pub const _SIZE: usize = SIZE as usize;
 
+// Format of the surface index image, see SurfaceIndex
+pub const SURFACE_INDEX_FORMAT: vk::Format = vk::Format::R32G32_UINT;
+
+// Bump allocator + the sizes of the 6 free lists (one per face count) + overflow counter + surface voxel count + texel budget
+pub const COUNTER_BUFFER_ELEMENTS: usize = 10;
+pub const OVERFLOW_COUNTER: usize = 7;
+pub const SURFACE_VOXEL_COUNTER: usize = 8;
+pub const TEXEL_BUDGET_COUNTER: usize = 9;
+
+// Cached lighting of a single texel of a face, must match SurfaceTexel in surface.slang
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SurfaceTexel {
+    pub shadow: vek::Vec4<u8>,
+    pub gi: u32,
+    pub lights: u32,
+    pub visibility: f32,
+}
+
+// Number of texels along each edge of a face in the surface buffer
+// Passed to the shaders as the SURFACE_RESOLUTION specialization constant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceResolution {
+    X2,
+    X4,
+    X8,
+}
+
+impl SurfaceResolution {
+    // Picked from the SURFACE_RESOLUTION env var (2, 4 or 8), defaults to 4x4
+    pub fn from_env() -> Self {
+        match std::env::var("SURFACE_RESOLUTION").as_deref() {
+            Ok("2") => Self::X2,
+            Ok("4") | Err(_) => Self::X4,
+            Ok("8") => Self::X8,
+            Ok(other) => {
+                log::warn!("invalid surface resolution '{other}', must be 2, 4 or 8. using 4");
+                Self::X4
+            }
+        }
+    }
+
+    pub fn edge(self) -> u32 {
+        match self {
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+        }
+    }
+
+    pub fn texels(self) -> u32 {
+        self.edge() * self.edge()
+    }
+
+    // Size of the surface data of a single face
+    pub fn face_size(self) -> usize {
+        size_of::<SurfaceTexel>() * self.texels() as usize
+    }
+}
+
+// Number of faces the surface buffer can initially store (semi-worst case scenario?)
+pub const INITIAL_SURFACE_CAPACITY: u32 = SIZE * SIZE * SIZE / 64 * 6;
+
+// Surface buffer utilization at which we start complaining
+pub const SURFACE_UTILIZATION_WARNING: f32 = 0.75;
+
+// Surface buffer utilization at which we reallocate it with twice the capacity
+pub const SURFACE_UTILIZATION_GROW: f32 = 0.9;
+
+// Must match the value in voxel.slang
+pub const FREE_LIST_CAPACITY: usize = 4096;
+
+// Sun epoch of texels that must be recomputed ASAP, must match SURFACE_STALE in surface.slang
+pub const SURFACE_STALE: u32 = 0xFF;
+
+// Angle (in radians) the sun can move before all the cached shadows are considered stale
+pub const SHADOW_SUN_STALE_ANGLE: f32 = 0.02;
+
+// Angle (in radians) the sun can jump in a single frame before we resolve all the cached shadows
+pub const SHADOW_SUN_RESOLVE_ANGLE: f32 = 0.3;
+
+// Distance (in voxels) around edits in which the cached shadows are invalidated
+pub const SHADOW_INVALIDATION_RADIUS: u32 = 16;
+
+// Controls how much work the shadow update does every tick
+pub struct ShadowSettings {
+    // Max number of texels updated per tick (over all faces)
+    pub texel_budget: u32,
+
+    // Voxels closer than this get updated every tick
+    pub near_distance: f32,
+
+    // Voxels further than this get updated every far_interval ticks
+    pub far_distance: f32,
+    pub far_interval: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            texel_budget: 64 * 1024,
+            near_distance: 16.0,
+            far_distance: 64.0,
+            far_interval: 8,
+        }
+    }
+}
+
+// Quality presets of the GI radiance cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl GiQuality {
+    // Hemisphere rays traced per texel per update
+    pub fn rays(self) -> u32 {
+        match self {
+            GiQuality::Low => 1,
+            GiQuality::Medium => 2,
+            GiQuality::High => 4,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            GiQuality::Low => GiQuality::Medium,
+            GiQuality::Medium => GiQuality::High,
+            GiQuality::High => GiQuality::Low,
+        }
+    }
+}
+
+// Controls the multi-bounce GI stored in the surface cache
+// Emissive voxels light their surroundings through it, so disabling it leaves them with only the block light
+pub struct GiSettings {
+    pub enabled: bool,
+    pub quality: GiQuality,
+
+    // Multiplier applied to the cached radiance when shading
+    pub strength: f32,
+}
+
+impl Default for GiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            quality: GiQuality::Medium,
+            strength: 1.0,
+        }
+    }
+}
+
+impl GiSettings {
+    pub fn rays(&self) -> u32 {
+        if self.enabled { self.quality.rays() } else { 0 }
+    }
+
+    pub fn strength(&self) -> f32 {
+        if self.enabled { self.strength } else { 0.0 }
+    }
+}
+
+// Quality presets of the cached sky visibility
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkyVisibilityQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl SkyVisibilityQuality {
+    // Rays that travel further than this without hitting anything can see the sky
+    pub fn distance(self) -> f32 {
+        match self {
+            SkyVisibilityQuality::Low => 8.0,
+            SkyVisibilityQuality::Medium => 16.0,
+            SkyVisibilityQuality::High => 32.0,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SkyVisibilityQuality::Low => SkyVisibilityQuality::Medium,
+            SkyVisibilityQuality::Medium => SkyVisibilityQuality::High,
+            SkyVisibilityQuality::High => SkyVisibilityQuality::Low,
+        }
+    }
+}
+
+// Max number of upward rays traced per texel per update
+pub const MAX_SKY_VISIBILITY_RAYS: u32 = 16;
+
+// Controls the coarse sky visibility term stored in the surface cache
+pub struct SkyVisibilitySettings {
+    pub enabled: bool,
+    pub quality: SkyVisibilityQuality,
+
+    // Upward hemisphere rays traced per texel per update
+    pub rays: u32,
+}
+
+impl Default for SkyVisibilitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            quality: SkyVisibilityQuality::Medium,
+            rays: 2,
+        }
+    }
+}
+
+impl SkyVisibilitySettings {
+    pub fn rays(&self) -> u32 {
+        if self.enabled { self.rays } else { 0 }
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.quality.distance()
+    }
+
+    // The renderer ignores the cached visibility when disabled
+    pub fn strength(&self) -> f32 {
+        if self.enabled { 1.0 } else { 0.0 }
+    }
+
+    // Doubles the ray count, wrapping back to a single ray
+    pub fn next_rays(&mut self) {
+        self.rays = if self.rays >= MAX_SKY_VISIBILITY_RAYS { 1 } else { self.rays * 2 };
+    }
+}
+
+// Culling, budget, GI and sky visibility settings of the update kernel. They only change when toggled
+// so they live in a buffer instead of the push constants. Must match UpdateSettings in voxel.slang
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct UpdateSettings {
+    pub texel_budget: u32,
+    pub near_distance: f32,
+    pub far_distance: f32,
+    pub far_interval: u32,
+    pub gi_rays: u32,
+    pub sky_rays: u32,
+    pub sky_distance: f32,
+    pub _padding: u32,
+}
+
+impl UpdateSettings {
+    pub fn new(shadow: &ShadowSettings, gi: &GiSettings, sky_visibility: &SkyVisibilitySettings) -> Self {
+        Self {
+            texel_budget: shadow.texel_budget,
+            near_distance: shadow.near_distance,
+            far_distance: shadow.far_distance,
+            far_interval: shadow.far_interval,
+            gi_rays: gi.rays(),
+            sky_rays: sky_visibility.rays(),
+            sky_distance: sky_visibility.distance(),
+            _padding: 0,
+        }
+    }
+}
+
+// Keeps track of the sun direction the cached shadows were computed for
+pub struct ShadowCache {
+    pub sun: vek::Vec3<f32>,
+    pub epoch: u32,
+    pub resolve: bool,
+}
+
+impl ShadowCache {
+    // Everything gets resolved on the first tick
+    pub fn new(sun: vek::Vec3<f32>) -> Self {
+        Self {
+            sun,
+            epoch: 0,
+            resolve: true,
+        }
+    }
+
+    // Bumps the epoch (marking every texel as stale) when the sun moved too far from the cached direction
+    pub fn track_sun(&mut self, sun: vek::Vec3<f32>, last: vek::Vec3<f32>) {
+        if last.angle_between(sun) > SHADOW_SUN_RESOLVE_ANGLE {
+            self.resolve = true;
+        }
+
+        if self.sun.angle_between(sun) > SHADOW_SUN_STALE_ANGLE {
+            self.sun = sun;
+            self.epoch = (self.epoch + 1) % SURFACE_STALE;
+
+            // Texels that were left untouched since the last time we had this epoch would look fresh, so resolve everything
+            if self.epoch == 0 {
+                self.resolve = true;
+            }
+        }
+    }
+}
+
+// Inclusive region of the voxel volume whose surface data must be recomputed
+#[derive(Clone, Copy, Debug)]
+pub struct DirtyRegion {
+    pub min: vek::Vec3<u32>,
+    pub max: vek::Vec3<u32>,
+}
+
+impl DirtyRegion {
+    // Covers the whole volume. Used for the initial surface allocation
+    pub fn full() -> Self {
+        Self {
+            min: vek::Vec3::zero(),
+            max: vek::Vec3::broadcast(SIZE - 1),
+        }
+    }
+
+    // Covers the given voxel and its direct neighbours
+    pub fn around(position: vek::Vec3<u32>) -> Self {
+        Self {
+            min: position.map(|x| x.saturating_sub(1)),
+            max: position.map(|x| (x + 1).min(SIZE - 1)),
+        }
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            min: vek::Vec3::partial_min(self.min, other.min),
+            max: vek::Vec3::partial_max(self.max, other.max),
+        }
+    }
+
+    // Grows the region by the given amount of voxels in every direction
+    pub fn expand(self, radius: u32) -> Self {
+        Self {
+            min: self.min.map(|x| x.saturating_sub(radius)),
+            max: self.max.map(|x| (x + radius).min(SIZE - 1)),
+        }
+    }
+
+    pub fn extent(&self) -> vek::Vec3<u32> {
+        self.max - self.min + 1
+    }
+}
+
+// Max level of both block light and sky light, they are stored as nibbles in the light image
+pub const LIGHT_LEVELS: u32 = 15;
+
+// Number of flood fill iterations we run every tick while the light has not converged yet
+pub const LIGHT_PASSES_PER_TICK: u32 = 8;
+
+// Keeps track of the part of the light volume that is still propagating
+pub struct LightPropagation {
+    pub region: Option<DirtyRegion>,
+    pub passes: u32,
+}
+
+impl LightPropagation {
+    // Sky light must travel down the whole volume at the start
+    pub fn new() -> Self {
+        Self {
+            region: Some(DirtyRegion::full()),
+            passes: SIZE + LIGHT_LEVELS,
+        }
+    }
+
+    // Placing or removing a voxel can change the light up to LIGHT_LEVELS voxels away
+    // and can cast (or remove) a sky light shadow all the way down to the bottom of the volume
+    pub fn mark(&mut self, region: DirtyRegion) {
+        let mut region = region.expand(LIGHT_LEVELS);
+        region.min.y = 0;
+        let region = self.region.map_or(region, |old| old.merge(region));
+        self.region = Some(region);
+        self.passes = region.extent().y + LIGHT_LEVELS;
+    }
+
+    // Returns the region and number of passes to run this tick
+    pub fn take(&mut self) -> Option<(DirtyRegion, u32)> {
+        let region = self.region?;
+        let passes = self.passes.min(LIGHT_PASSES_PER_TICK);
+        self.passes -= passes;
+
+        if self.passes == 0 {
+            self.region = None;
+        }
+
+        Some((region, passes))
+    }
+}
+
 pub unsafe fn create_voxel_image(
     device: &ash::Device,
     allocator: &mut Allocator,
@@ -78,15 +466,14 @@ pub unsafe fn create_voxel_surface_buffer(
     device: &ash::Device,
     allocator: &mut Allocator,
     binder: &Option<ash::ext::debug_utils::Device>,
+    resolution: SurfaceResolution,
+    capacity: u32,
 ) -> (vk::Buffer, Allocation) {
-    // store semi-worst case scenario?
-    const SOME_ARBITRARY_SIZE_FOR_MAX_NUMBER_OF_CUBES_IDK: usize = _SIZE*_SIZE*_SIZE / 64;
-    let size = size_of::<vek::Vec4<u8>>() * 6 * 16 * SOME_ARBITRARY_SIZE_FOR_MAX_NUMBER_OF_CUBES_IDK;
-
+    let size = resolution.face_size() * capacity as usize;
 
     let voxel_buffer_create_info = vk::BufferCreateInfo::default()
         .flags(vk::BufferCreateFlags::empty())
-        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .size(size as u64);
     let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
@@ -116,6 +503,66 @@ pub unsafe fn create_voxel_surface_buffer(
     (buffer, allocation)
 }
 
+// Creates a bigger surface buffer and copies the contents of the old one into it
+// Block indices stay the same so the surface index image is still valid afterwards
+pub unsafe fn grow_voxel_surface_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    queue: vk::Queue,
+    pool: vk::CommandPool,
+    binder: &Option<ash::ext::debug_utils::Device>,
+    resolution: SurfaceResolution,
+    old: (vk::Buffer, Allocation),
+    old_capacity: u32,
+    new_capacity: u32,
+) -> (vk::Buffer, Allocation) {
+    let new = create_voxel_surface_buffer(device, allocator, binder, resolution, new_capacity);
+
+    let cmd_buffer_create_info = vk::CommandBufferAllocateInfo::default()
+        .command_buffer_count(1)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(pool);
+    let cmd = device
+        .allocate_command_buffers(&cmd_buffer_create_info)
+        .unwrap()[0];
+
+    let cmd_buffer_begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device
+        .begin_command_buffer(cmd, &cmd_buffer_begin_info)
+        .unwrap();
+
+    let region = vk::BufferCopy::default()
+        .src_offset(0)
+        .dst_offset(0)
+        .size((resolution.face_size() * old_capacity as usize) as u64);
+    device.cmd_copy_buffer(cmd, old.0, new.0, &[region]);
+
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
+
+    device.end_command_buffer(cmd).unwrap();
+
+    let cmds = [cmd];
+    let submit_info = vk::SubmitInfo::default()
+        .command_buffers(&cmds);
+
+    let fence = device.create_fence(&Default::default(), None).unwrap();
+    device.queue_submit(queue, &[submit_info], fence).unwrap();
+    device.wait_for_fences(&[fence], true, u64::MAX).unwrap();
+    device.free_command_buffers(pool, &[cmd]);
+    device.destroy_fence(fence, None);
+
+    device.destroy_buffer(old.0, None);
+    allocator.free(old.1).unwrap();
+    new
+}
 
 pub unsafe fn create_voxel_counter_buffer(
     device: &ash::Device,
@@ -124,9 +571,9 @@ pub unsafe fn create_voxel_counter_buffer(
 ) -> (vk::Buffer, Allocation) {
     let voxel_buffer_create_info = vk::BufferCreateInfo::default()
         .flags(vk::BufferCreateFlags::empty())
-        .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
-        .size(size_of::<u32>() as u64);
+        .size((size_of::<u32>() * COUNTER_BUFFER_ELEMENTS) as u64);
     let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
 
     let requirements = device.get_buffer_memory_requirements(buffer);
@@ -153,6 +600,203 @@ pub unsafe fn create_voxel_counter_buffer(
     (buffer, allocation)
 }
 
+// Host visible copy of the counter buffer that gets written to at the end of every tick
+pub unsafe fn create_voxel_counter_readback_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let voxel_buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size((size_of::<u32>() * COUNTER_BUFFER_ELEMENTS) as u64);
+    let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Voxel Counter Readback Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::GpuToCpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"voxel counter readback buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+    
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Fetches the contents of the counter buffer from the last tick
+pub fn read_voxel_counters(readback: &Allocation) -> [u32; COUNTER_BUFFER_ELEMENTS] {
+    let raw = &readback.mapped_slice().unwrap()[..size_of::<u32>() * COUNTER_BUFFER_ELEMENTS];
+    let mut counters = [0u32; COUNTER_BUFFER_ELEMENTS];
+    bytemuck::cast_slice_mut::<u32, u8>(&mut counters).copy_from_slice(raw);
+    counters
+}
+
+pub unsafe fn create_voxel_free_list_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let voxel_buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size((size_of::<u32>() * 6 * FREE_LIST_CAPACITY) as u64);
+    let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Voxel Free List Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"voxel free list buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+    
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Packed coordinates of all the voxels that have surface data (worst case is every single voxel)
+pub unsafe fn create_voxel_surface_list_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let voxel_buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size((size_of::<u32>() * _SIZE * _SIZE * _SIZE) as u64);
+    let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Voxel Surface List Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"voxel surface list buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+    
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Indirect dispatch arguments of the update kernel, written on the GPU by the prepare kernel
+pub unsafe fn create_voxel_dispatch_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let voxel_buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<vk::DispatchIndirectCommand>() as u64);
+    let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Voxel Dispatch Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"voxel dispatch buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+    
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+// Host visible so the settings can be toggled at any time
+pub unsafe fn create_voxel_update_settings_buffer(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    binder: &Option<ash::ext::debug_utils::Device>,
+) -> (vk::Buffer, Allocation) {
+    let voxel_buffer_create_info = vk::BufferCreateInfo::default()
+        .flags(vk::BufferCreateFlags::empty())
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .size(size_of::<UpdateSettings>() as u64);
+    let buffer = device.create_buffer(&voxel_buffer_create_info, None).unwrap();
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = allocator
+        .allocate(&gpu_allocator::vulkan::AllocationCreateDesc {
+            name: "Voxel Update Settings Allocation",
+            requirements: requirements,
+            linear: true,
+            allocation_scheme: gpu_allocator::vulkan::AllocationScheme::DedicatedBuffer(buffer),
+            location: gpu_allocator::MemoryLocation::CpuToGpu,
+        })
+        .unwrap();
+
+    if let Some(binder) = binder {
+        let marker = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(buffer)
+            .object_name(c"voxel update settings buffer");
+        binder.set_debug_utils_object_name(&marker).unwrap();
+    }
+
+    let device_memory = allocation.memory();
+    device.bind_buffer_memory(buffer, device_memory, 0).unwrap();
+    (buffer, allocation)
+}
+
+pub fn upload_update_settings(allocation: &mut Allocation, settings: &UpdateSettings) {
+    let raw = bytemuck::bytes_of(settings);
+    allocation.mapped_slice_mut().unwrap()[..raw.len()].copy_from_slice(raw);
+}
+
 pub unsafe fn generate_voxel_image(
     device: &ash::Device,
     queue: vk::Queue,
@@ -163,6 +807,8 @@ pub unsafe fn generate_voxel_image(
     voxel_image_view: vk::ImageView,
     voxel_indices_image: vk::Image,
     voxel_indices_image_view: vk::ImageView,
+    voxel_light_image: vk::Image,
+    counter_buffer: vk::Buffer,
     descriptor_set_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
@@ -209,10 +855,35 @@ pub unsafe fn generate_voxel_image(
         .dst_queue_family_index(queue_family_index)
         .image(voxel_indices_image)
         .subresource_range(subresource_range);
-    let image_memory_barriers = [first_transition, first_transition_amogus];
+    let first_transition_light = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_access_mask(vk::AccessFlags2::NONE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_WRITE | vk::AccessFlags2::TRANSFER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .src_queue_family_index(queue_family_index)
+        .dst_queue_family_index(queue_family_index)
+        .image(voxel_light_image)
+        .subresource_range(subresource_range);
+    let image_memory_barriers = [first_transition, first_transition_amogus, first_transition_light];
     let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
     device.cmd_pipeline_barrier2(cmd, &dep);
 
+    // No voxel has surface data allocated at the start, and all the free lists are empty
+    let [block_index, enabled_faces] = SurfaceIndex::INVALID.into_raw();
+    let clear_color_value = vk::ClearColorValue {
+        uint32: [block_index, enabled_faces, 0, 0],
+    };
+    device.cmd_clear_color_image(cmd, voxel_indices_image, vk::ImageLayout::GENERAL, &clear_color_value, &[subresource_range]);
+
+    // Everything starts dark, the light propagation fills it up over the first few ticks
+    let clear_color_value = vk::ClearColorValue {
+        uint32: [0, 0, 0, 0],
+    };
+    device.cmd_clear_color_image(cmd, voxel_light_image, vk::ImageLayout::GENERAL, &clear_color_value, &[subresource_range]);
+    device.cmd_fill_buffer(cmd, counter_buffer, 0, vk::WHOLE_SIZE, 0);
+
     let layouts = [descriptor_set_layout];
     let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
         .descriptor_pool(descriptor_pool)
@@ -267,7 +938,15 @@ pub unsafe fn generate_voxel_image(
         .image(voxel_image)
         .subresource_range(subresource_range);
     let image_memory_barriers = [second_transition];
-    let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default()
+        .image_memory_barriers(&image_memory_barriers)
+        .memory_barriers(&barriers);
     device.cmd_pipeline_barrier2(cmd, &dep);
 
     device.end_command_buffer(cmd).unwrap();
@@ -296,13 +975,30 @@ pub unsafe fn update_voxel_thingies(
     queue_family_index: u32,
     surface_buffer: vk::Buffer,
     counter_buffer: vk::Buffer,
+    readback_buffer: vk::Buffer,
+    free_list_buffer: vk::Buffer,
+    surface_list_buffer: vk::Buffer,
+    dispatch_buffer: vk::Buffer,
+    point_light_buffer: vk::Buffer,
+    update_settings_buffer: vk::Buffer,
     voxel_image: vk::Image,
     voxel_image_view: vk::ImageView,
     voxel_indices_image: vk::Image,
     voxel_indices_image_view: vk::ImageView,
+    voxel_light_image: vk::Image,
+    voxel_light_image_view: vk::ImageView,
     descriptor_set_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    release: (vk::PipelineLayout, vk::Pipeline),
+    allocate: (vk::PipelineLayout, vk::Pipeline),
+    invalidate: (vk::PipelineLayout, vk::Pipeline),
+    compact: (vk::PipelineLayout, vk::Pipeline),
+    prepare: (vk::PipelineLayout, vk::Pipeline),
+    propagate: (vk::PipelineLayout, vk::Pipeline),
+    dirty: Option<DirtyRegion>,
+    light: Option<(DirtyRegion, u32)>,
+    capacity: u32,
     push_constants: PushConstants2,
 ) -> vk::DescriptorSet {
 
@@ -335,6 +1031,17 @@ pub unsafe fn update_voxel_thingies(
         .dst_queue_family_index(queue_family_index)
         .image(voxel_indices_image)
         .subresource_range(subresource_range);
+    let voxel_light_image_read_to_write = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::GENERAL)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::MEMORY_READ)
+        .dst_access_mask(vk::AccessFlags2::SHADER_WRITE | vk::AccessFlags2::SHADER_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .src_queue_family_index(queue_family_index)
+        .dst_queue_family_index(queue_family_index)
+        .image(voxel_light_image)
+        .subresource_range(subresource_range);
     let voxel_surface_buffer_read_to_write = vk::BufferMemoryBarrier2::default()
         .buffer(surface_buffer)
         .size(u64::MAX)
@@ -356,7 +1063,7 @@ pub unsafe fn update_voxel_thingies(
         .src_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::MEMORY_READ)
         .dst_access_mask(vk::AccessFlags2::SHADER_WRITE);
 
-    let image_memory_barriers = [voxel_image_read_to_write, voxel_indices_image_read_to_write];
+    let image_memory_barriers = [voxel_image_read_to_write, voxel_indices_image_read_to_write, voxel_light_image_read_to_write];
     let buffer_memory_barriers = [voxel_surface_buffer_read_to_write, voxel_counter_buffer_read_to_write];
     let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers).buffer_memory_barriers(&buffer_memory_barriers);
     device.cmd_pipeline_barrier2(cmd, &dep);
@@ -394,6 +1101,42 @@ pub unsafe fn update_voxel_thingies(
         .range(u64::MAX);
     let descriptor_buffer_counter_infos = [descriptor_buffer_counter_info];
 
+    let descriptor_buffer_free_list_info = vk::DescriptorBufferInfo::default()
+        .buffer(free_list_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_buffer_free_list_infos = [descriptor_buffer_free_list_info];
+
+    let descriptor_buffer_surface_list_info = vk::DescriptorBufferInfo::default()
+        .buffer(surface_list_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_buffer_surface_list_infos = [descriptor_buffer_surface_list_info];
+
+    let descriptor_buffer_dispatch_info = vk::DescriptorBufferInfo::default()
+        .buffer(dispatch_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_buffer_dispatch_infos = [descriptor_buffer_dispatch_info];
+
+    let descriptor_buffer_point_light_info = vk::DescriptorBufferInfo::default()
+        .buffer(point_light_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_buffer_point_light_infos = [descriptor_buffer_point_light_info];
+
+    let descriptor_light_image_info = vk::DescriptorImageInfo::default()
+        .image_view(voxel_light_image_view)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .sampler(vk::Sampler::null());
+    let descriptor_light_image_infos = [descriptor_light_image_info];
+
+    let descriptor_buffer_update_settings_info = vk::DescriptorBufferInfo::default()
+        .buffer(update_settings_buffer)
+        .offset(0)
+        .range(u64::MAX);
+    let descriptor_buffer_update_settings_infos = [descriptor_buffer_update_settings_info];
+
     let descriptor_write_1 = vk::WriteDescriptorSet::default()
         .descriptor_count(1)
         .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
@@ -422,8 +1165,171 @@ pub unsafe fn update_voxel_thingies(
         .dst_set(descriptor_set)
         .buffer_info(&descriptor_buffer_counter_infos);
 
+    let descriptor_write_5 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(4)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_buffer_free_list_infos);
+
+    let descriptor_write_6 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(5)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_buffer_surface_list_infos);
+
+    let descriptor_write_7 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(6)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_buffer_dispatch_infos);
+
+    let descriptor_write_8 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(7)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_buffer_point_light_infos);
+
+    let descriptor_write_9 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .dst_binding(8)
+        .dst_set(descriptor_set)
+        .image_info(&descriptor_light_image_infos);
+
+    let descriptor_write_10 = vk::WriteDescriptorSet::default()
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .dst_binding(9)
+        .dst_set(descriptor_set)
+        .buffer_info(&descriptor_buffer_update_settings_infos);
+
     device
-        .update_descriptor_sets(&[descriptor_write_1, descriptor_write_2, descriptor_write_3, descriptor_write_4], &[]);
+        .update_descriptor_sets(&[descriptor_write_1, descriptor_write_2, descriptor_write_3, descriptor_write_4, descriptor_write_5, descriptor_write_6, descriptor_write_7, descriptor_write_8, descriptor_write_9, descriptor_write_10], &[]);
+
+    // Recompute the surface allocations of the modified voxels (and their neighbours)
+    // and rebuild the surface voxel list from scratch
+    if let Some(dirty) = dirty {
+        device.cmd_fill_buffer(cmd, counter_buffer, (OVERFLOW_COUNTER * size_of::<u32>()) as u64, size_of::<u32>() as u64, 0);
+        device.cmd_fill_buffer(cmd, counter_buffer, (SURFACE_VOXEL_COUNTER * size_of::<u32>()) as u64, size_of::<u32>() as u64, 0);
+
+        let barrier = vk::MemoryBarrier2::default()
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+        let barriers = [barrier];
+        let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+        device.cmd_pipeline_barrier2(cmd, &dep);
+
+        // Edits can also change the shadows of voxels further away
+        let invalidated = dirty.expand(SHADOW_INVALIDATION_RADIUS);
+
+        for ((pipeline_layout, pipeline), region) in [(release, dirty), (allocate, dirty), (invalidate, invalidated), (compact, DirtyRegion::full())] {
+            let push_constants = PushConstants3 {
+                region_min: region.min.with_w(0),
+                region_max: region.max.with_w(0),
+                capacity,
+            };
+            let raw = bytemuck::bytes_of(&push_constants);
+            let groups = region.extent().map(|x| (x + 7) / 8);
+
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline_layout,
+                0,
+                &descriptor_sets,
+                &[],
+            );
+        
+            device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline,
+            );
+
+            device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, raw);
+            device.cmd_dispatch(cmd, groups.x, groups.y, groups.z);
+
+            let barrier = vk::MemoryBarrier2::default()
+                .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+            let barriers = [barrier];
+            let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+            device.cmd_pipeline_barrier2(cmd, &dep);
+        }
+
+        let (pipeline_layout, pipeline) = prepare;
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &descriptor_sets,
+            &[],
+        );
+        device.cmd_bind_pipeline(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline,
+        );
+        device.cmd_dispatch(cmd, 1, 1, 1);
+
+        let barrier = vk::MemoryBarrier2::default()
+            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags2::INDIRECT_COMMAND_READ | vk::AccessFlags2::SHADER_READ)
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_stage_mask(vk::PipelineStageFlags2::DRAW_INDIRECT | vk::PipelineStageFlags2::COMPUTE_SHADER);
+        let barriers = [barrier];
+        let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+        device.cmd_pipeline_barrier2(cmd, &dep);
+    }
+
+    // Flood fill the block and sky light a few steps at a time, each pass moves the light by one voxel
+    if let Some((region, passes)) = light {
+        let (pipeline_layout, pipeline) = propagate;
+        let push_constants = PushConstants3 {
+            region_min: region.min.with_w(0),
+            region_max: region.max.with_w(0),
+            capacity,
+        };
+        let raw = bytemuck::bytes_of(&push_constants);
+        let groups = region.extent().map(|x| (x + 7) / 8);
+
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &descriptor_sets,
+            &[],
+        );
+        device.cmd_bind_pipeline(
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline,
+        );
+        device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, raw);
+
+        for _ in 0..passes {
+            device.cmd_dispatch(cmd, groups.x, groups.y, groups.z);
+
+            let barrier = vk::MemoryBarrier2::default()
+                .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
+            let barriers = [barrier];
+            let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+            device.cmd_pipeline_barrier2(cmd, &dep);
+        }
+    }
 
     device.cmd_bind_descriptor_sets(
         cmd,
@@ -440,24 +1346,23 @@ pub unsafe fn update_voxel_thingies(
         pipeline,
     );
 
-    let data = [0u32];
-    let raw = bytemuck::cast_slice::<u32, u8>(&data);
+    let raw = bytemuck::bytes_of(&push_constants);
+    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, raw);
 
-    device.cmd_update_buffer(cmd, counter_buffer, 0, raw);
+    // Every tick starts with the full texel budget
+    device.cmd_fill_buffer(cmd, counter_buffer, (TEXEL_BUDGET_COUNTER * size_of::<u32>()) as u64, size_of::<u32>() as u64, 0);
 
     let barrier = vk::MemoryBarrier2::default()
         .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
-        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::MEMORY_READ)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE)
         .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
         .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
     let barriers = [barrier];
     let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
     device.cmd_pipeline_barrier2(cmd, &dep);
-
-    let raw = bytemuck::bytes_of(&push_constants);
-    device.cmd_push_constants(cmd, pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, raw);
     
-    device.cmd_dispatch(cmd, SIZE / 8, SIZE / 8, SIZE / 8);
+    // Only runs over the voxels in the surface voxel list
+    device.cmd_dispatch_indirect(cmd, dispatch_buffer, 0);
 
     let voxel_image_write_to_read = vk::ImageMemoryBarrier2::default()
         .old_layout(vk::ImageLayout::GENERAL)
@@ -481,6 +1386,17 @@ pub unsafe fn update_voxel_thingies(
         .dst_queue_family_index(queue_family_index)
         .image(voxel_indices_image)
         .subresource_range(subresource_range);
+    let voxel_light_image_write_to_read = vk::ImageMemoryBarrier2::default()
+        .old_layout(vk::ImageLayout::GENERAL)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::MEMORY_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        .src_queue_family_index(queue_family_index)
+        .dst_queue_family_index(queue_family_index)
+        .image(voxel_light_image)
+        .subresource_range(subresource_range);
     let voxel_surface_buffer_write_to_read = vk::BufferMemoryBarrier2::default()
         .buffer(surface_buffer)
         .size(u64::MAX)
@@ -501,10 +1417,26 @@ pub unsafe fn update_voxel_thingies(
         .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
         .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
         .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::MEMORY_READ);
-    let image_memory_barriers = [voxel_image_write_to_read, voxel_indices_image_write_to_read];
+    let image_memory_barriers = [voxel_image_write_to_read, voxel_indices_image_write_to_read, voxel_light_image_write_to_read];
     let buffer_memory_barriers = [voxel_surface_buffer_write_to_read, voxel_counter_buffer_write_to_read];
     let dep = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers).buffer_memory_barriers(&buffer_memory_barriers);
     device.cmd_pipeline_barrier2(cmd, &dep);
+
+    // Read back the counters so the CPU knows how full the surface buffer is
+    let region = vk::BufferCopy::default()
+        .src_offset(0)
+        .dst_offset(0)
+        .size((size_of::<u32>() * COUNTER_BUFFER_ELEMENTS) as u64);
+    device.cmd_copy_buffer(cmd, counter_buffer, readback_buffer, &[region]);
+
+    let barrier = vk::MemoryBarrier2::default()
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags2::HOST_READ)
+        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+        .dst_stage_mask(vk::PipelineStageFlags2::HOST);
+    let barriers = [barrier];
+    let dep = vk::DependencyInfo::default().memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(cmd, &dep);
     return descriptor_set;
 }
 
@@ -598,10 +1530,51 @@ pub struct Voxel {
     pub reflective: bool,
     pub refractive: bool,
     pub placed: bool,
+    pub emissive: bool,
 }
 
 impl Voxel {
     pub fn into_raw(self) -> u8 {
-        self.active as u8 | (self.reflective as u8) << 1 | (self.refractive as u8) << 2 | (self.placed as u8) << 3
+        self.active as u8 | (self.reflective as u8) << 1 | (self.refractive as u8) << 2 | (self.placed as u8) << 3 | (self.emissive as u8) << 4
+    }
+}
+
+// Entry of the surface index image. Must match the definition in surface.slang
+// Contains the index of the surface data of the first enabled face and the enabled faces bitset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceIndex {
+    pub block_index: u32,
+    pub enabled_faces: u8,
+}
+
+impl SurfaceIndex {
+    pub const INVALID: Self = Self {
+        block_index: u32::MAX,
+        enabled_faces: 0,
+    };
+
+    pub fn into_raw(self) -> [u32; 2] {
+        [self.block_index, self.enabled_faces as u32]
     }
-}
\ No newline at end of file
+
+    pub fn from_raw(raw: [u32; 2]) -> Self {
+        Self {
+            block_index: raw[0],
+            enabled_faces: (raw[1] & 0b111111) as u8,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.block_index != u32::MAX
+    }
+
+    pub fn face_count(&self) -> u32 {
+        self.enabled_faces.count_ones()
+    }
+
+    // Index of the surface data of the given face in the surface buffer (if the face is enabled)
+    pub fn face_index(&self, face: u32) -> Option<u32> {
+        let enabled = self.is_valid() && face < 6 && (self.enabled_faces >> face) & 1 == 1;
+        enabled.then(|| self.block_index + (self.enabled_faces & ((1u8 << face) - 1)).count_ones())
+    }
+}